@@ -0,0 +1,142 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+///how far into the document a <meta> charset declaration is looked for,
+/// same as the browser prescan.
+const PRESCAN_LENGTH: usize = 1024;
+
+///public function:
+/// "text/html; charset=Shift_JIS" -> "Shift_JIS"
+pub fn charset_from_content_type(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches(['"', '\'']).to_string())
+        } else {
+            None
+        }
+    })
+}
+
+///public function:
+/// charset named by a <meta charset> or <meta http-equiv="content-type">
+/// in the first 1024 bytes of the document.
+pub fn charset_from_meta(body: &[u8]) -> Option<String> {
+    lazy_static! {
+        static ref META_RE: Regex =
+            Regex::new(r#"(?i-u)<meta[^>]*?charset\s*=\s*["']?\s*([a-z0-9_.:-]+)"#).unwrap();
+    }
+    let head = &body[..body.len().min(PRESCAN_LENGTH)];
+    let captures = META_RE.captures(head)?;
+    Some(String::from_utf8_lossy(captures.get(1)?.as_bytes()).into_owned())
+}
+
+///public function:
+/// picks the encoding of an html document the way browsers do:
+/// byte order mark, then the content type header, then the <meta> prescan,
+/// then utf-8 when the bytes are valid utf-8 and windows-1252 otherwise.
+pub fn detect_html_encoding(body: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type
+        .and_then(charset_from_content_type)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+    {
+        return encoding;
+    }
+    if let Some(encoding) =
+        charset_from_meta(body).and_then(|label| Encoding::for_label(label.as_bytes()))
+    {
+        //a document that could be read to find the meta is not utf-16
+        return if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+            UTF_8
+        } else {
+            encoding.output_encoding()
+        };
+    }
+    if std::str::from_utf8(body).is_ok() {
+        UTF_8
+    } else {
+        WINDOWS_1252
+    }
+}
+
+///public function:
+/// decodes an html document to a string, see detect_html_encoding.
+pub fn decode_html(body: &[u8], content_type: Option<&str>) -> String {
+    let (text, _, _) = detect_html_encoding(body, content_type).decode(body);
+    text.into_owned()
+}
+
+///public function:
+/// picks the encoding of a stylesheet in css syntax order:
+/// byte order mark, the content type header, a leading @charset rule,
+/// then the encoding of whatever linked it (the page or the importing sheet),
+/// utf-8 when none of those say anything.
+pub fn detect_css_encoding(
+    body: &[u8],
+    content_type: Option<&str>,
+    referrer: Option<&str>,
+) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type
+        .and_then(charset_from_content_type)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+    {
+        return encoding;
+    }
+    if let Some(encoding) = charset_rule(body).and_then(Encoding::for_label) {
+        //a sheet that could be read to find the rule is not utf-16
+        return if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+            UTF_8
+        } else {
+            encoding
+        };
+    }
+    referrer
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8)
+}
+
+///public function:
+/// decodes a stylesheet (see detect_css_encoding) and drops its @charset rule,
+/// the text is utf-8 from here on. also returns the encoding it was read with,
+/// the referrer encoding of the sheets it imports.
+pub fn decode_css(
+    body: &[u8],
+    content_type: Option<&str>,
+    referrer: Option<&str>,
+) -> (String, &'static Encoding) {
+    let encoding = detect_css_encoding(body, content_type, referrer);
+    let (text, _, _) = encoding.decode(body);
+    let text = match text.strip_prefix("@charset \"") {
+        Some(rest) => match rest.split_once("\";") {
+            Some((_, rest)) => rest.to_string(),
+            None => text.into_owned(),
+        },
+        None => text.into_owned(),
+    };
+    (text, encoding)
+}
+
+///the label of an @charset "label"; rule at the very start of the bytes.
+fn charset_rule(body: &[u8]) -> Option<&[u8]> {
+    let rest = body.strip_prefix(b"@charset \"")?;
+    let head = &rest[..rest.len().min(PRESCAN_LENGTH)];
+    let end = head.windows(2).position(|pair| pair == b"\";")?;
+    Some(&rest[..end])
+}
+
+///decodes with the given charset (utf-8 when missing or unknown),
+/// a byte order mark wins over the label.
+pub fn decode_text(body: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
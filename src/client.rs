@@ -1,49 +1,508 @@
-use crate::{html::HtmlRecord, web_archiver::replace_encoded_chars};
+use crate::charset::{
+    charset_from_content_type, decode_css, decode_html, decode_text, detect_html_encoding,
+};
+use crate::layout::{HostPathLayout, SnapshotLayout};
+use crate::{error::Error, html::HtmlRecord};
 use bytes::Bytes;
+use futures::future::join_all;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, LOCATION};
+use reqwest::{redirect, Proxy, Response};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 use url::Url;
 
+pub static DEFAULT_USER_AGENT: &str = concat!("lolchive/", env!("CARGO_PKG_VERSION"));
+
+///settings for the shared http client.
+/// build one, tweak the fields, and hand it to HttpClient::new.
+#[derive(Debug, Clone)]
+pub struct ArchiverConfig {
+    ///total time allowed for a single request, body included.
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    pub user_agent: String,
+    ///extra headers sent with every request.
+    pub headers: Vec<(String, String)>,
+    ///proxy url used for every scheme, e.g. "socks5://127.0.0.1:9050".
+    pub proxy: Option<String>,
+    ///0 turns redirects off.
+    pub max_redirects: usize,
+    ///bodies bigger than this are refused, none means no limit.
+    pub max_body_size: Option<usize>,
+    ///when set, save_page also writes a png thumbnail no bigger than
+    /// this many pixels on either side into thumbnails/ for every decodable image.
+    pub thumbnail_size: Option<u32>,
+    ///where under the base path each snapshot goes, see layout::HostPathLayout.
+    pub layout: Arc<dyn SnapshotLayout>,
+    ///save_page keeps every asset once in base_path/objects (keyed by sha-256)
+    /// and hard links it into the snapshot, false writes a separate copy per snapshot.
+    pub object_store: bool,
+    ///how many assets of one page are downloaded at the same time.
+    pub max_concurrent_downloads: usize,
+    ///how many of those may go to the same host.
+    pub max_downloads_per_host: usize,
+}
+
+impl Default for ArchiverConfig {
+    fn default() -> Self {
+        ArchiverConfig {
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: vec![],
+            proxy: None,
+            max_redirects: 10,
+            max_body_size: Some(50 * 1024 * 1024),
+            thumbnail_size: None,
+            layout: Arc::new(HostPathLayout),
+            object_store: true,
+            max_concurrent_downloads: 8,
+            max_downloads_per_host: 4,
+        }
+    }
+}
+
+///one reqwest client shared by every fetch so connections are reused.
+/// cheap to clone, clones share the same connection pool.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    ///same settings but never follows redirects, for when the chain is wanted.
+    manual_client: reqwest::Client,
+    headers: HeaderMap,
+    pub config: ArchiverConfig,
+}
+
+impl HttpClient {
+    pub fn new(config: ArchiverConfig) -> Result<Self, Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::Parse(format!("header name {}: {}", name, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::Parse(format!("header value {}: {}", value, e)))?;
+            headers.insert(name, value);
+        }
+
+        let redirect_policy = if config.max_redirects == 0 {
+            redirect::Policy::none()
+        } else {
+            redirect::Policy::limited(config.max_redirects)
+        };
+
+        let build = |policy: redirect::Policy| -> Result<reqwest::Client, Error> {
+            let mut builder = reqwest::Client::builder()
+                .timeout(config.timeout)
+                .connect_timeout(config.connect_timeout)
+                .user_agent(config.user_agent.clone())
+                .default_headers(headers.clone())
+                .redirect(policy);
+            if let Some(proxy) = &config.proxy {
+                builder = builder.proxy(Proxy::all(proxy)?);
+            }
+            Ok(builder.build()?)
+        };
+
+        Ok(HttpClient {
+            client: build(redirect_policy)?,
+            manual_client: build(redirect::Policy::none())?,
+            headers,
+            config,
+        })
+    }
+
+    ///plain get, the status is left for the caller to check.
+    pub async fn get(&self, url_str: &str) -> Result<Response, Error> {
+        let url_parsed = Url::parse(url_str)?;
+        Ok(self.client.get(url_parsed).send().await?)
+    }
+
+    ///get that follows redirects itself (up to max_redirects)
+    /// and hands back every url that answered with a redirect, in order.
+    /// when the limit is hit the last redirect response is returned as is.
+    pub async fn get_with_redirects(
+        &self,
+        url_str: &str,
+    ) -> Result<(Response, Vec<String>), Error> {
+        let mut url = Url::parse(url_str)?;
+        let mut redirects: Vec<String> = vec![];
+        loop {
+            let res = self.manual_client.get(url.clone()).send().await?;
+            if !res.status().is_redirection() || redirects.len() >= self.config.max_redirects {
+                return Ok((res, redirects));
+            }
+            let next = match res
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+            {
+                Some(next) => next,
+                None => return Ok((res, redirects)),
+            };
+            redirects.push(url.to_string());
+            url = next;
+        }
+    }
+
+    ///reads the whole body, stopping early once max_body_size is passed.
+    pub async fn read_body(&self, mut res: Response) -> Result<Bytes, Error> {
+        let limit = match self.config.max_body_size {
+            Some(limit) => limit,
+            None => return Ok(res.bytes().await?),
+        };
+        let too_large = |res: &Response| Error::BodyTooLarge {
+            url: res.url().to_string(),
+            limit,
+        };
+        if res.content_length().unwrap_or(0) as usize > limit {
+            return Err(too_large(&res));
+        }
+
+        let mut body: Vec<u8> = vec![];
+        while let Some(chunk) = res.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(too_large(&res));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Bytes::from(body))
+    }
+}
+
+impl Default for HttpClient {
+    ///default config, panics only if the tls backend can't be initialised.
+    fn default() -> Self {
+        HttpClient::new(ArchiverConfig::default()).expect("failed to build default http client")
+    }
+}
+
+///raw http exchange,
+/// keeps everything needed to write the request and response
+/// out again (used by the warc writer).
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+    ///the redirect responses that led here, in order.
+    /// each is its own exchange with an empty redirects list.
+    pub redirects: Vec<RawResponse>,
+}
+
+impl RawResponse {
+    ///the request as it went over the wire, minus the body.
+    pub fn request_block(&self) -> Vec<u8> {
+        let target = match Url::parse(&self.url) {
+            Ok(url) => match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            },
+            Err(_) => self.url.clone(),
+        };
+        let mut block = format!("GET {} {}\r\n", target, self.version);
+        for (name, value) in &self.request_headers {
+            block.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        block.push_str("\r\n");
+        block.into_bytes()
+    }
+
+    ///the first response header called name (any case).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    ///body decoded as an html document (byte order mark,
+    /// content-type header, then <meta> charset).
+    pub fn text(&self) -> String {
+        decode_html(&self.body, self.header("content-type"))
+    }
+
+    ///status line, headers and body of the response.
+    pub fn response_block(&self) -> Vec<u8> {
+        let mut block = format!("{} {} {}\r\n", self.version, self.status, self.reason);
+        for (name, value) in &self.headers {
+            block.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        block.push_str("\r\n");
+        let mut block = block.into_bytes();
+        block.extend_from_slice(&self.body);
+        block
+    }
+}
+
+///a downloaded asset, the bytes are exactly what the server sent.
+#[derive(Debug, Clone)]
+pub struct Asset {
+    pub url: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub bytes: Bytes,
+}
+
+impl Asset {
+    ///body decoded with the charset from the content-type header (utf-8 otherwise).
+    pub fn text(&self) -> String {
+        let charset = self
+            .content_type
+            .as_deref()
+            .and_then(charset_from_content_type);
+        decode_text(&self.bytes, charset.as_deref())
+    }
+
+    ///body decoded as a stylesheet, @charset rule dropped (see charset::decode_css).
+    /// referrer is the encoding of the page or sheet that linked it,
+    /// the encoding used is handed back for the sheets this one imports.
+    pub fn css_text(&self, referrer: Option<&str>) -> (String, &'static str) {
+        let (css, encoding) = decode_css(&self.bytes, self.content_type.as_deref(), referrer);
+        (css, encoding.name())
+    }
+}
+
 ///public function
 /// takes in a url string (complete "https://example.com")
-/// returns an HtmlDocument if good, reqwest error if bad
+/// returns an HtmlDocument if good, Error if bad
 /// basically just assembles after making a client side call
-pub async fn fetch_html_record(url_str: &str) -> Result<HtmlRecord, reqwest::Error> {
-    let url_parsed = Url::parse(url_str).expect("cannot parse");
-    let res = reqwest::get(url_parsed.as_str()).await?;
-    let _status_value = res.status().as_u16();
-    let body = res.text().await.expect("unable to parse html text");
-    let body = replace_encoded_chars(body);
-    let record: HtmlRecord = HtmlRecord::new(url_parsed.to_string(), body);
+pub async fn fetch_html_record(client: &HttpClient, url_str: &str) -> Result<HtmlRecord, Error> {
+    let started = Instant::now();
+    let (res, redirects) = client.get_with_redirects(url_str).await?;
+    let status = res.status().as_u16();
+    let final_url = res.url().to_string();
+    let headers: Vec<(String, String)> = res
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect();
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let bytes = client.read_body(res).await?;
+    let encoding = detect_html_encoding(&bytes, content_type.as_deref());
+    let (body, _, _) = encoding.decode(&bytes);
+    let mut record: HtmlRecord =
+        HtmlRecord::new(Url::parse(url_str)?.to_string(), body.into_owned());
+    record.charset = Some(encoding.name().to_string());
+    record.status = Some(status);
+    record.headers = headers;
+    record.final_url = Some(final_url);
+    record.redirects = redirects;
+    record.fetch_duration = Some(started.elapsed());
+    record.content_length = Some(bytes.len() as u64);
 
     Ok(record)
 }
 
-pub async fn fetch_image_bytes(url_str: &str) -> Result<Bytes, String> {
-    let url_parsed = Url::parse(url_str).expect("cannot parse");
-    let res = reqwest::get(url_parsed.as_str())
-        .await
-        .expect("error getting requested image");
+///public function
+/// fetches a url and keeps the status line, headers and raw body bytes.
+/// the body is not decoded in any way so it can be stored byte for byte.
+/// redirects are followed here (up to max_redirects) rather than by reqwest,
+/// every hop is kept in RawResponse::redirects.
+pub async fn fetch_raw(client: &HttpClient, url_str: &str) -> Result<RawResponse, Error> {
+    let mut url = Url::parse(url_str)?;
+    let mut redirects: Vec<RawResponse> = vec![];
+    loop {
+        let mut raw = fetch_raw_hop(client, &url).await?;
+        let next = raw
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+            .and_then(|(_, location)| url.join(location).ok());
+        match next {
+            Some(next)
+                if (300..400).contains(&raw.status)
+                    && redirects.len() < client.config.max_redirects =>
+            {
+                redirects.push(raw);
+                url = next;
+            }
+            _ => {
+                raw.redirects = redirects;
+                return Ok(raw);
+            }
+        }
+    }
+}
+
+///one request with manual_client, redirects are left to fetch_raw.
+async fn fetch_raw_hop(client: &HttpClient, url_parsed: &Url) -> Result<RawResponse, Error> {
+    let request = client.manual_client.get(url_parsed.as_str()).build()?;
+
+    let mut request_headers: Vec<(String, String)> = vec![];
+    if let Some(host) = url_parsed.host_str() {
+        match url_parsed.port() {
+            Some(port) => request_headers.push(("Host".to_string(), format!("{}:{}", host, port))),
+            None => request_headers.push(("Host".to_string(), host.to_string())),
+        }
+    }
+    request_headers.push(("user-agent".to_string(), client.config.user_agent.clone()));
+    for (name, value) in request.headers().iter().chain(
+        client
+            .headers
+            .iter()
+            .filter(|(name, _)| !request.headers().contains_key(*name)),
+    ) {
+        request_headers.push((name.to_string(), value.to_str().unwrap_or("").to_string()));
+    }
+
+    let res = client.manual_client.execute(request).await?;
+    let version = format!("{:?}", res.version());
+    let status = res.status();
+    let headers = res
+        .headers()
+        .iter()
+        //the body is stored de-chunked, so the framing header would be a lie
+        .filter(|(name, _)| name.as_str() != "transfer-encoding")
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect();
+    let body = client.read_body(res).await?;
+
+    Ok(RawResponse {
+        url: url_parsed.to_string(),
+        request_headers,
+        version,
+        status: status.as_u16(),
+        reason: status.canonical_reason().unwrap_or("").to_string(),
+        headers,
+        body,
+        redirects: vec![],
+    })
+}
+
+///public function
+/// fetches any asset and keeps its content type next to the untouched bytes.
+pub async fn fetch_asset(client: &HttpClient, url_str: &str) -> Result<Asset, Error> {
+    let res = client.get(url_str).await?;
 
     let status_value = res.status().as_u16();
 
     if status_value == 200 {
-        let image_value = res.bytes().await.expect("unable to parse html text");
-        Ok(image_value)
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        Ok(Asset {
+            url: url_str.to_string(),
+            status: status_value,
+            content_type,
+            bytes: client.read_body(res).await?,
+        })
     } else {
-        Err("status on image call not a 200 OKAY".to_string())
+        Err(Error::HttpStatus {
+            url: url_str.to_string(),
+            status: status_value,
+        })
+    }
+}
+
+///public function:
+/// downloads all the urls concurrently, at most config.max_concurrent_downloads
+/// at once and config.max_downloads_per_host to any one host.
+/// every url is fetched once, results are keyed by url so the caller can
+/// handle them in document order whatever order they finished in.
+pub async fn fetch_assets(
+    client: &HttpClient,
+    urls: &[String],
+) -> HashMap<String, Result<Asset, Error>> {
+    fetch_limited(client, urls, |url| fetch_asset(client, url)).await
+}
+
+///public function:
+/// fetch_assets for raw exchanges (see fetch_raw), same limits.
+pub async fn fetch_raws(
+    client: &HttpClient,
+    urls: &[String],
+) -> HashMap<String, Result<RawResponse, Error>> {
+    fetch_limited(client, urls, |url| fetch_raw(client, url)).await
+}
+
+///runs fetch for every url under the download limits of the config.
+async fn fetch_limited<'a, T, F, Fut>(
+    client: &HttpClient,
+    urls: &'a [String],
+    fetch: F,
+) -> HashMap<String, Result<T, Error>>
+where
+    F: Fn(&'a str) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let slots = Semaphore::new(client.config.max_concurrent_downloads.max(1));
+    let mut host_limits: HashMap<String, Arc<Semaphore>> = HashMap::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut downloads = vec![];
+    for url in urls {
+        if !seen.insert(url.as_str()) {
+            continue;
+        }
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(String::from))
+            .unwrap_or_default();
+        let limit = host_limits
+            .entry(host)
+            .or_insert_with(|| {
+                Arc::new(Semaphore::new(client.config.max_downloads_per_host.max(1)))
+            })
+            .clone();
+        let slots = &slots;
+        let download = fetch(url);
+        downloads.push(async move {
+            //host first, so a download waiting on a busy host doesn't hold
+            //one of the global slots other hosts could use. neither is ever closed
+            let _host_permit = limit.acquire().await;
+            let _permit = slots.acquire().await;
+            (url.clone(), download.await)
+        });
     }
+    join_all(downloads).await.into_iter().collect()
 }
 
-pub async fn fetch_string_resource(url_str: &str) -> Result<String, String> {
-    let url_parsed = Url::parse(url_str).expect("cannot parse");
-    let res = reqwest::get(url_parsed.as_str())
-        .await
-        .expect("error getting requested image");
+pub async fn fetch_image_bytes(client: &HttpClient, url_str: &str) -> Result<Bytes, Error> {
+    Ok(fetch_asset(client, url_str).await?.bytes)
+}
+
+pub async fn fetch_string_resource(client: &HttpClient, url_str: &str) -> Result<String, Error> {
+    let res = client.get(url_str).await?;
     let status_value = res.status().as_u16();
 
     if status_value == 200 {
-        let css = res.text().await.expect("unable to parse html text");
-        Ok(css)
+        let charset = charset_from_headers(res.headers());
+        Ok(decode_text(
+            &client.read_body(res).await?,
+            charset.as_deref(),
+        ))
     } else {
-        Err("status on css call not a 200 OKAY".to_string())
+        Err(Error::HttpStatus {
+            url: url_str.to_string(),
+            status: status_value,
+        })
     }
 }
+
+///charset parameter of the content-type header, if any.
+fn charset_from_headers(headers: &HeaderMap) -> Option<String> {
+    charset_from_content_type(headers.get(CONTENT_TYPE)?.to_str().ok()?)
+}
@@ -1,181 +1,164 @@
-use crate::client;
+use crate::client::ArchiverConfig;
+use crate::error::Error;
+use crate::fetcher::{
+    BrowserConfig, FantocciniFetcher, HybridFetcher, PageFetcher, ReqwestFetcher,
+};
 use crate::html::HtmlRecord;
-use crate::web_archiver::{get_capabilities, replace_encoded_chars, save_page};
-use fantoccini::{Client, ClientBuilder};
+use crate::render::RenderRules;
+use crate::snapshot::ArchiveResult;
+use crate::web_archiver::archive_page;
 use regex::Regex;
-use std::time::Duration;
+use std::collections::HashSet;
 
-pub struct FantocciniCrawler {
-    pub fclient: Client,
+///crawls a site with any PageFetcher, archiving every page it visits.
+/// BasicCrawler (reqwest) and FantocciniCrawler (webdriver) come with the crate,
+/// Crawler::with_fetcher takes your own.
+pub struct Crawler<F: PageFetcher> {
+    pub fetcher: F,
 }
 
-impl FantocciniCrawler {
-    pub async fn new(connection_string: &str) -> Result<Self, String> {
-        let client = ClientBuilder::native()
-            .capabilities(get_capabilities())
-            .connect(connection_string)
-            .await
-            .unwrap_or_else(|_| panic!("failed to connect to WebDriver on {}", connection_string));
+pub type BasicCrawler = Crawler<ReqwestFetcher>;
+pub type FantocciniCrawler = Crawler<FantocciniFetcher>;
+pub type HybridCrawler = Crawler<HybridFetcher>;
 
-        Ok(FantocciniCrawler { fclient: client })
+impl<F: PageFetcher> Crawler<F> {
+    pub fn with_fetcher(fetcher: F) -> Self {
+        Crawler { fetcher }
     }
 
+    ///archives up to num_of_pages pages of the site starting at url,
+    /// one result per page tried, failures included.
     pub async fn save_crawl(
         &self,
         url: &str,
         directory: &str,
         num_of_pages: usize,
-    ) -> Result<Vec<String>, String> {
-        let mut visited: Vec<String> = vec![url.to_string()];
-        let mut i: usize = 0;
-        let mut ret_vec: Vec<String> = vec![];
-
-        while i < num_of_pages && i < visited.len() {
-            if self.fclient.goto(&visited[i]).await.is_err() {
-                i += 1;
-                continue;
-            }
-            let _ = self.fclient.wait().at_most(Duration::from_secs(10));
-
-            if let Ok(body) = self.fclient.source().await {
-                let body = replace_encoded_chars(body);
-                let record = HtmlRecord::new(visited[i].to_string(), body);
-                if let Some(links) = record.domain_anchors() {
-                    for link in links {
-                        if !visited.contains(&link) {
-                            visited.push(link)
-                        }
-                    }
-                }
-
-                if let Ok(image) = self.fclient.screenshot().await {
-                    if let Ok(path) = save_page(record, directory, Some(image)).await {
-                        ret_vec.push(path);
-                    }
-                } else if let Ok(path) = save_page(record, directory, None).await {
-                    ret_vec.push(path);
-                }
-            }
-            i += 1;
-        }
-        Ok(ret_vec)
+    ) -> Result<Vec<ArchiveResult>, Error> {
+        self.crawl(url, directory, num_of_pages, |record| {
+            record.domain_anchors()
+        })
+        .await
     }
 
+    ///same as save_crawl but only follows links matching reg.
     pub async fn crawl_curate(
         &self,
         url: &str,
         directory: &str,
         num_of_pages: usize,
         reg: &str,
-    ) -> Result<Vec<String>, String> {
-        let mut visited: Vec<String> = vec![url.to_string()];
-        let mut i: usize = 0;
-        let mut ret_vec: Vec<String> = vec![];
-        let regex = match Regex::new(reg) {
-            Ok(re) => re,
-            Err(e) => return Err(e.to_string()),
-        };
-
-        while i < num_of_pages && i < visited.len() {
-            if self.fclient.goto(&visited[i]).await.is_err() {
-                i += 1;
-                continue;
-            }
-            let _ = self.fclient.wait().at_most(Duration::from_secs(10));
-
-            if let Ok(body) = self.fclient.source().await {
-                let body = replace_encoded_chars(body);
-                let record = HtmlRecord::new(visited[i].to_string(), body);
-                if let Some(links) = record.anchors_curate(regex.clone()) {
-                    for link in links {
-                        if !visited.contains(&link) {
-                            visited.push(link)
-                        }
-                    }
-                }
-
-                if let Ok(image) = self.fclient.screenshot().await {
-                    if let Ok(path) = save_page(record, directory, Some(image)).await {
-                        ret_vec.push(path);
-                    }
-                } else if let Ok(path) = save_page(record, directory, None).await {
-                    ret_vec.push(path);
-                }
-            }
-            i += 1;
-        }
-        Ok(ret_vec)
-    }
-    pub async fn close(self) -> Result<(), String> {
-        if self.fclient.close().await.is_ok() {
-            Ok(())
-        } else {
-            Err(
-                "closing the archiver did not work, exit the program and restart the geckodriver"
-                    .to_string(),
-            )
-        }
+    ) -> Result<Vec<ArchiveResult>, Error> {
+        let regex = Regex::new(reg)?;
+        self.crawl(url, directory, num_of_pages, |record| {
+            record.anchors_curate(regex.clone())
+        })
+        .await
     }
-}
 
-pub struct BasicCrawler {}
-
-impl BasicCrawler {
-    pub async fn save_crawl(
+    ///same as crawl_curate, the name BasicCrawler has always had.
+    pub async fn crawl_curated(
+        &self,
         url: &str,
         directory: &str,
         num_of_pages: usize,
-    ) -> Result<Vec<String>, String> {
-        let mut visited: Vec<String> = vec![url.to_string()];
-        let mut i: usize = 0;
-        let mut ret_vec: Vec<String> = vec![];
-
-        while i < num_of_pages && i < visited.len() {
-            if let Ok(record) = client::fetch_html_record(&visited[i]).await {
-                if let Some(links) = record.domain_anchors() {
-                    for link in links {
-                        if !visited.contains(&link) {
-                            visited.push(link)
-                        }
-                    }
-                }
-                if let Ok(path) = save_page(record, directory, None).await {
-                    ret_vec.push(path);
-                }
-            }
-            i += 1;
-        }
-        Ok(ret_vec)
+        reg: &str,
+    ) -> Result<Vec<ArchiveResult>, Error> {
+        self.crawl_curate(url, directory, num_of_pages, reg).await
     }
-    pub async fn crawl_curated(
+
+    async fn crawl<L>(
+        &self,
         url: &str,
         directory: &str,
         num_of_pages: usize,
-        reg: &str,
-    ) -> Result<Vec<String>, String> {
+        links: L,
+    ) -> Result<Vec<ArchiveResult>, Error>
+    where
+        L: Fn(&HtmlRecord) -> Option<HashSet<String>>,
+    {
         let mut visited: Vec<String> = vec![url.to_string()];
         let mut i: usize = 0;
-        let mut ret_vec: Vec<String> = vec![];
-        let regex = match Regex::new(reg) {
-            Ok(re) => re,
-            Err(e) => return Err(e.to_string()),
-        };
+        let mut results: Vec<ArchiveResult> = vec![];
 
         while i < num_of_pages && i < visited.len() {
-            if let Ok(record) = client::fetch_html_record(&visited[i]).await {
-                if let Some(links) = record.anchors_curate(regex.clone()) {
-                    for link in links {
-                        if !visited.contains(&link) {
-                            visited.push(link)
-                        }
-                    }
+            let page_url = visited[i].clone();
+            i += 1;
+            let page = match self.fetcher.fetch(&page_url).await {
+                Ok(page) => page,
+                Err(e) => {
+                    results.push(ArchiveResult::failed(&page_url, e));
+                    continue;
                 }
-                if let Ok(path) = save_page(record, directory, None).await {
-                    ret_vec.push(path);
+            };
+            for link in links(&page.record).unwrap_or_default() {
+                if !visited.contains(&link) {
+                    visited.push(link)
                 }
             }
-            i += 1;
+
+            let result = match archive_page(self.fetcher.client(), page, directory).await {
+                Ok(result) => result,
+                Err(e) => ArchiveResult::failed(&page_url, e),
+            };
+            results.push(result);
         }
-        Ok(ret_vec)
+        Ok(results)
+    }
+}
+
+impl Crawler<ReqwestFetcher> {
+    pub fn new(config: ArchiverConfig) -> Result<Self, Error> {
+        Ok(Crawler::with_fetcher(ReqwestFetcher::new(config)?))
+    }
+}
+
+impl Default for Crawler<ReqwestFetcher> {
+    fn default() -> Self {
+        Crawler::with_fetcher(ReqwestFetcher::default())
+    }
+}
+
+impl Crawler<FantocciniFetcher> {
+    pub async fn new(connection_string: &str) -> Result<Self, Error> {
+        Ok(Crawler::with_fetcher(
+            FantocciniFetcher::new(connection_string).await?,
+        ))
+    }
+
+    pub async fn with_config(
+        connection_string: &str,
+        config: ArchiverConfig,
+        browser_config: BrowserConfig,
+    ) -> Result<Self, Error> {
+        Ok(Crawler::with_fetcher(
+            FantocciniFetcher::with_config(connection_string, config, browser_config).await?,
+        ))
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.fetcher.close().await
+    }
+}
+
+impl Crawler<HybridFetcher> {
+    pub async fn new(connection_string: &str) -> Result<Self, Error> {
+        Ok(Crawler::with_fetcher(
+            HybridFetcher::new(connection_string).await?,
+        ))
+    }
+
+    pub async fn with_config(
+        connection_string: &str,
+        config: ArchiverConfig,
+        browser_config: BrowserConfig,
+        rules: RenderRules,
+    ) -> Result<Self, Error> {
+        Ok(Crawler::with_fetcher(
+            HybridFetcher::with_config(connection_string, config, browser_config, rules).await?,
+        ))
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.fetcher.close().await
     }
 }
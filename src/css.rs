@@ -0,0 +1,98 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+
+///a url found inside a stylesheet.
+/// start and end are the byte range of the url text itself (quotes excluded)
+/// so it can be swapped out without touching anything around it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CssReference {
+    pub url: String,
+    pub start: usize,
+    pub end: usize,
+    ///true for @import targets, those are stylesheets themselves.
+    pub import: bool,
+}
+
+///public function:
+/// finds every url(...) and @import target in a stylesheet,
+/// in the order they appear. data: uris and fragment references are skipped.
+pub fn css_references(css: &str) -> Vec<CssReference> {
+    lazy_static! {
+        static ref URL_RE: Regex =
+            Regex::new(r#"(?i)url\(\s*(?:"([^"]*)"|'([^']*)'|([^)"'\s]*))\s*\)"#).unwrap();
+        static ref IMPORT_RE: Regex =
+            Regex::new(r#"(?i)@import\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    }
+    let mut references: Vec<CssReference> = vec![];
+
+    for captures in URL_RE.captures_iter(css) {
+        let whole = captures.get(0).unwrap();
+        if let Some(url) = (1..=3).find_map(|i| captures.get(i)) {
+            references.push(CssReference {
+                url: url.as_str().trim().to_string(),
+                start: url.start(),
+                end: url.end(),
+                import: ends_with_import(&css[..whole.start()]),
+            });
+        }
+    }
+    for captures in IMPORT_RE.captures_iter(css) {
+        if let Some(url) = (1..=2).find_map(|i| captures.get(i)) {
+            references.push(CssReference {
+                url: url.as_str().trim().to_string(),
+                start: url.start(),
+                end: url.end(),
+                import: true,
+            });
+        }
+    }
+
+    references.retain(|reference| is_fetchable(&reference.url));
+    references.sort_by_key(|reference| reference.start);
+    references
+}
+
+///public function:
+/// replaces the urls found by css_references with the values in replacements
+/// (keyed by the url as written), anything not in the map is left as is.
+pub fn rewrite_css(css: &str, replacements: &HashMap<String, String>) -> String {
+    let mut rewritten = String::with_capacity(css.len());
+    let mut last = 0;
+    for reference in css_references(css) {
+        if let Some(replacement) = replacements.get(&reference.url) {
+            rewritten.push_str(&css[last..reference.start]);
+            rewritten.push_str(replacement);
+            last = reference.end;
+        }
+    }
+    rewritten.push_str(&css[last..]);
+    rewritten
+}
+
+///what kind of file a css reference points at, decides the snapshot subdirectory.
+pub fn css_asset_directory(url: &str, import: bool) -> &'static str {
+    if import {
+        return "css";
+    }
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+    match path.rsplit('.').next() {
+        Some("woff") | Some("woff2") | Some("ttf") | Some("otf") | Some("eot") => "fonts",
+        Some("css") => "css",
+        _ => "images",
+    }
+}
+
+fn ends_with_import(before: &str) -> bool {
+    let before = before.trim_end().as_bytes();
+    before.len() >= 7 && before[before.len() - 7..].eq_ignore_ascii_case(b"@import")
+}
+
+fn is_fetchable(url: &str) -> bool {
+    let lower = url.to_lowercase();
+    !(url.is_empty()
+        || url.starts_with('#')
+        || lower.starts_with("data:")
+        || lower.starts_with("about:")
+        || lower.starts_with("javascript:"))
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+///every error the crate hands back to the caller.
+#[derive(Debug)]
+pub enum Error {
+    ///the request itself failed (dns, connect, tls, body read...).
+    Network(reqwest::Error),
+    ///the server answered but not with a status we can use.
+    HttpStatus { url: String, status: u16 },
+    ///the body was bigger than the configured max_body_size.
+    BodyTooLarge { url: String, limit: usize },
+    ///something could not be parsed (selector, regex, image, html...).
+    Parse(String),
+    ///reading or writing the archive on disk failed.
+    Io(std::io::Error),
+    ///the webdriver (geckodriver) could not be reached or a command failed.
+    WebDriver(String),
+    ///the url could not be parsed or is missing a part we need (host...).
+    InvalidUrl(String),
+    ///waited too long for something (a page to load, an element to appear...).
+    Timeout(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(e) => write!(f, "network error: {}", e),
+            Error::HttpStatus { url, status } => write!(f, "http status {} for {}", status, url),
+            Error::BodyTooLarge { url, limit } => {
+                write!(f, "body of {} is larger than {} bytes", url, limit)
+            }
+            Error::Parse(e) => write!(f, "parse error: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::WebDriver(e) => write!(f, "webdriver error: {}", e),
+            Error::InvalidUrl(e) => write!(f, "invalid url: {}", e),
+            Error::Timeout(e) => write!(f, "timed out: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::InvalidUrl(e.to_string())
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<fantoccini::error::CmdError> for Error {
+    fn from(e: fantoccini::error::CmdError) -> Self {
+        Error::WebDriver(e.to_string())
+    }
+}
+
+impl From<fantoccini::error::NewSessionError> for Error {
+    fn from(e: fantoccini::error::NewSessionError) -> Self {
+        Error::WebDriver(e.to_string())
+    }
+}
@@ -0,0 +1,343 @@
+use crate::charset::detect_html_encoding;
+use crate::client::{fetch_html_record, fetch_raw, ArchiverConfig, Asset, HttpClient, RawResponse};
+use crate::error::Error;
+use crate::html::HtmlRecord;
+use crate::print::{print_page, PrintConfig};
+use crate::render::{RenderMode, RenderRules};
+use crate::screenshot::{self, ScreenshotConfig};
+use crate::wait::WaitConfig;
+use crate::web_archiver::get_capabilities;
+use fantoccini::{Client, ClientBuilder};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
+use url::Url;
+
+///what PageFetcher::fetch hands back, scraper's Html is not Send so neither is this.
+pub type FetchFuture<'a> = Pin<Box<dyn Future<Output = Result<FetchedPage, Error>> + 'a>>;
+
+///one page as a fetcher got it.
+#[derive(Debug)]
+pub struct FetchedPage {
+    pub record: HtmlRecord,
+    pub screenshot: Option<Vec<u8>>,
+    ///the page printed to pdf, see BrowserConfig::print.
+    pub pdf: Option<Vec<u8>>,
+    ///things that went wrong without stopping the fetch (no screenshot...).
+    pub warnings: Vec<String>,
+    ///assets the fetcher already has, keyed by absolute url.
+    /// save_page uses them instead of downloading, everything else is fetched with client().
+    pub resources: HashMap<String, Asset>,
+    ///the http exchange of the page itself, only set by fetch_exchange.
+    pub response: Option<RawResponse>,
+}
+
+impl FetchedPage {
+    ///a page with nothing but its record.
+    pub fn new(record: HtmlRecord) -> Self {
+        FetchedPage {
+            record,
+            screenshot: None,
+            pdf: None,
+            warnings: vec![],
+            resources: HashMap::new(),
+            response: None,
+        }
+    }
+}
+
+///how a page gets from a url to an HtmlRecord.
+/// Archiver and Crawler do the rest (assets, rewriting, snapshot layout) the same
+/// for every fetcher, so a new backend only has to implement this.
+pub trait PageFetcher {
+    ///public method:
+    /// loads the page at url.
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a>;
+
+    ///public method:
+    /// like fetch but also keeps the raw http exchange of the page
+    /// (FetchedPage::response) for warc output. fetchers that have none,
+    /// a browser for one, leave the default.
+    fn fetch_exchange<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        self.fetch(url)
+    }
+
+    ///the client the assets of fetched pages are downloaded with.
+    fn client(&self) -> &HttpClient;
+}
+
+///pages straight from reqwest, no javascript.
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestFetcher {
+    pub client: HttpClient,
+}
+
+impl ReqwestFetcher {
+    pub fn new(config: ArchiverConfig) -> Result<Self, Error> {
+        Ok(ReqwestFetcher {
+            client: HttpClient::new(config)?,
+        })
+    }
+}
+
+impl PageFetcher for ReqwestFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let record = fetch_html_record(&self.client, url).await?;
+            Ok(FetchedPage::new(record))
+        })
+    }
+
+    fn fetch_exchange<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let raw = fetch_raw(&self.client, url).await?;
+            let mut record = HtmlRecord::new(Url::parse(url)?.to_string(), raw.text());
+            record.status = Some(raw.status);
+            record.headers = raw.headers.clone();
+            record.content_length = Some(raw.body.len() as u64);
+            record.charset = Some(
+                detect_html_encoding(&raw.body, raw.header("content-type"))
+                    .name()
+                    .to_string(),
+            );
+            record.final_url = Some(raw.url.clone());
+            record.redirects = raw.redirects.iter().map(|hop| hop.url.clone()).collect();
+            let mut page = FetchedPage::new(record);
+            page.response = Some(raw);
+            Ok(page)
+        })
+    }
+
+    fn client(&self) -> &HttpClient {
+        &self.client
+    }
+}
+
+///what only the browser fetchers use, the http side is ArchiverConfig.
+#[derive(Debug, Clone, Default)]
+pub struct BrowserConfig {
+    ///what the browser waits for before reading a page, see wait::WaitConfig.
+    pub wait: WaitConfig,
+    ///full page or viewport screenshots, viewport size and pixel ratio of the browser.
+    pub screenshot: ScreenshotConfig,
+    ///when set, every page is also printed to page.pdf.
+    pub print: Option<PrintConfig>,
+}
+
+///pages rendered by a webdriver (geckodriver) through fantoccini,
+/// with a screenshot of each.
+pub struct FantocciniFetcher {
+    pub fclient: Client,
+    ///used for the assets, the page itself comes from the browser.
+    pub client: HttpClient,
+    pub browser_config: BrowserConfig,
+}
+
+impl FantocciniFetcher {
+    pub async fn new(connection_string: &str) -> Result<Self, Error> {
+        FantocciniFetcher::with_config(
+            connection_string,
+            ArchiverConfig::default(),
+            BrowserConfig::default(),
+        )
+        .await
+    }
+
+    pub async fn with_config(
+        connection_string: &str,
+        config: ArchiverConfig,
+        browser_config: BrowserConfig,
+    ) -> Result<Self, Error> {
+        let mut capabilities = get_capabilities();
+        if let Some(ratio) = browser_config.screenshot.device_pixel_ratio {
+            screenshot::set_device_pixel_ratio(&mut capabilities, ratio);
+        }
+        let fclient = ClientBuilder::native()
+            .capabilities(capabilities)
+            .connect(connection_string)
+            .await?;
+        if let Some((width, height)) = browser_config.screenshot.viewport {
+            screenshot::set_viewport(&fclient, width, height).await?;
+        }
+
+        Ok(FantocciniFetcher {
+            fclient,
+            client: HttpClient::new(config)?,
+            browser_config,
+        })
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.fclient.close().await?;
+        Ok(())
+    }
+
+    ///navigates to url, waits as browser_config.wait says and builds the record from
+    /// the rendered source. the browser does not expose status or headers so only
+    /// the final url and the load time are filled in.
+    /// a wait that times out only becomes a warning, the page is read as it is.
+    async fn load_record(
+        &self,
+        url: &str,
+        warnings: &mut Vec<String>,
+    ) -> Result<HtmlRecord, Error> {
+        let started = Instant::now();
+        self.fclient.goto(url).await?;
+        match self.browser_config.wait.wait_for(&self.fclient, url).await {
+            Ok(()) => {}
+            Err(e @ Error::Timeout(_)) => warnings.push(e.to_string()),
+            Err(e) => return Err(e),
+        }
+
+        let body = self.fclient.source().await?;
+
+        let mut record = HtmlRecord::new(url.to_string(), body);
+        record.fetch_duration = Some(started.elapsed());
+        record.final_url = self.fclient.current_url().await.ok().map(String::from);
+        record.charset = self
+            .fclient
+            .execute("return document.characterSet", vec![])
+            .await
+            .ok()
+            .and_then(|charset| charset.as_str().map(String::from));
+        Ok(record)
+    }
+}
+
+impl PageFetcher for FantocciniFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(async move {
+            let mut warnings: Vec<String> = vec![];
+            let record = self.load_record(url, &mut warnings).await?;
+            let mut page = FetchedPage::new(record);
+            page.warnings = warnings;
+            //a failed screenshot only becomes a warning
+            match screenshot::capture(&self.fclient, &self.browser_config.screenshot).await {
+                Ok(image) => page.screenshot = Some(image),
+                Err(e) => page.warnings.push(format!("no screenshot: {}", e)),
+            }
+            if let Some(print) = &self.browser_config.print {
+                match print_page(&self.fclient, print).await {
+                    Ok(pdf) => page.pdf = Some(pdf),
+                    Err(e) => page.warnings.push(format!("no pdf: {}", e)),
+                }
+            }
+            Ok(page)
+        })
+    }
+
+    fn client(&self) -> &HttpClient {
+        &self.client
+    }
+}
+
+///reqwest first, the browser only for pages that need it (see RenderRules),
+/// so sites that render on the server don't pay for geckodriver.
+/// pages that were rendered carry a warning saying why.
+pub struct HybridFetcher {
+    pub reqwest: ReqwestFetcher,
+    pub browser: FantocciniFetcher,
+    pub rules: RenderRules,
+}
+
+impl HybridFetcher {
+    pub async fn new(connection_string: &str) -> Result<Self, Error> {
+        HybridFetcher::with_config(
+            connection_string,
+            ArchiverConfig::default(),
+            BrowserConfig::default(),
+            RenderRules::default(),
+        )
+        .await
+    }
+
+    pub async fn with_config(
+        connection_string: &str,
+        config: ArchiverConfig,
+        browser_config: BrowserConfig,
+        rules: RenderRules,
+    ) -> Result<Self, Error> {
+        let browser =
+            FantocciniFetcher::with_config(connection_string, config, browser_config).await?;
+        //both halves share one connection pool
+        let reqwest = ReqwestFetcher {
+            client: browser.client.clone(),
+        };
+        Ok(HybridFetcher {
+            reqwest,
+            browser,
+            rules,
+        })
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.browser.close().await
+    }
+
+    ///the reqwest page as it is, or the browser's capture of it.
+    /// hosts that are always rendered skip reqwest, and a page reqwest
+    /// could not get is tried in the browser unless its host is never rendered.
+    async fn fetch_page(&self, url: &str, exchange: bool) -> Result<FetchedPage, Error> {
+        let mode = self.rules.mode_for(url);
+        if mode == RenderMode::Always {
+            return self
+                .render(url, None, "host is always rendered".to_string())
+                .await;
+        }
+        let fetched = if exchange {
+            self.reqwest.fetch_exchange(url).await
+        } else {
+            self.reqwest.fetch(url).await
+        };
+        let page = match fetched {
+            Ok(page) => page,
+            Err(e) if mode == RenderMode::Never => return Err(e),
+            Err(e) => {
+                return self
+                    .render(url, None, format!("reqwest failed: {}", e))
+                    .await
+            }
+        };
+        match self.rules.needs_rendering(&page.record) {
+            Some(reason) => self.render(url, Some(page), reason).await,
+            None => Ok(page),
+        }
+    }
+
+    ///the browser's capture of url with a warning saying why.
+    /// it keeps the http status, headers, redirects and raw exchange
+    /// of the reqwest page when there is one, the browser can't tell those.
+    async fn render(
+        &self,
+        url: &str,
+        page: Option<FetchedPage>,
+        reason: String,
+    ) -> Result<FetchedPage, Error> {
+        let mut rendered = self.browser.fetch(url).await?;
+        if let Some(page) = page {
+            rendered.record.status = page.record.status;
+            rendered.record.headers = page.record.headers;
+            rendered.record.redirects = page.record.redirects;
+            rendered.response = page.response;
+        }
+        rendered
+            .warnings
+            .push(format!("rendered in the browser: {}", reason));
+        Ok(rendered)
+    }
+}
+
+impl PageFetcher for HybridFetcher {
+    fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(self.fetch_page(url, false))
+    }
+
+    fn fetch_exchange<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+        Box::pin(self.fetch_page(url, true))
+    }
+
+    fn client(&self) -> &HttpClient {
+        &self.browser.client
+    }
+}
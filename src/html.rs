@@ -1,18 +1,45 @@
-use chrono::Utc;
+use crate::error::Error;
+use chrono::{DateTime, SecondsFormat, Utc};
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::{
+    states, BufferQueue, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
 use lazy_static::lazy_static;
+use rand::{distributions::Alphanumeric, Rng};
 use regex::Regex;
 use scraper::{Html, Selector};
+use serde_json::{json, Value};
 use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use std::iter::FromIterator;
+use std::time::Duration;
 use substring::Substring;
 use url::{ParseError, Url};
 
 #[derive(Debug)]
 pub struct HtmlRecord {
     pub origin: String,
-    pub date_time: String,
+    ///when the page was captured.
+    pub date_time: DateTime<Utc>,
+    ///random id of this capture, keeps two captures in the same second apart.
+    pub snapshot_id: String,
     pub body: String,
     pub html: Html,
+    ///http status of the final response, none when the page came from the browser.
+    pub status: Option<u16>,
+    ///response headers of the final response, in the order they were sent.
+    pub headers: Vec<(String, String)>,
+    ///where the page actually ended up after redirects.
+    pub final_url: Option<String>,
+    ///every url that answered with a redirect on the way to final_url.
+    pub redirects: Vec<String>,
+    ///time from the request going out to the body being read.
+    pub fetch_duration: Option<Duration>,
+    ///size of the body as received, in bytes.
+    pub content_length: Option<u64>,
+    ///encoding the page was decoded from ("windows-1251"), stylesheets
+    /// without a charset of their own are read with it. none when unknown.
+    pub charset: Option<String>,
 }
 
 /*
@@ -25,12 +52,37 @@ impl HtmlRecord {
     pub fn new(origin: String, body: String) -> HtmlRecord {
         HtmlRecord {
             origin,
-            date_time: Utc::now().format("%d-%m-%Y-%H:%M:%S").to_string(),
+            date_time: Utc::now(),
+            snapshot_id: new_snapshot_id(),
             html: Html::parse_document(&body),
             body,
+            status: None,
+            headers: vec![],
+            final_url: None,
+            redirects: vec![],
+            fetch_duration: None,
+            content_length: None,
+            charset: None,
         }
     }
 
+    ///public method,
+    /// everything known about how the page was fetched, as written to metadata.json.
+    pub fn metadata(&self) -> Value {
+        json!({
+            "url": self.origin,
+            "final_url": self.final_url,
+            "date_time": self.date_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "snapshot_id": self.snapshot_id,
+            "status": self.status,
+            "redirects": self.redirects,
+            "headers": self.headers,
+            "fetch_duration_ms": self.fetch_duration.map(|duration| duration.as_millis() as u64),
+            "content_length": self.content_length,
+            "charset": self.charset,
+        })
+    }
+
     /// public function,
     /// input is a reference to self.
     /// returns a HashSet<String>,
@@ -41,7 +93,7 @@ impl HtmlRecord {
         for element in self.html.select(&selector) {
             match element.value().attr("href") {
                 Some(link) => {
-                    if let Ok(parse_link) = HtmlRecord::check_link(&self.origin, link) {
+                    if let Some(parse_link) = HtmlRecord::check_link(&self.origin, link) {
                         ret_vec.push(parse_link)
                     }
                 }
@@ -68,7 +120,7 @@ impl HtmlRecord {
         for element in self.html.select(&selector) {
             match element.value().attr("href") {
                 Some(link) => {
-                    if let Ok(parsed_link) = HtmlRecord::check_link(&self.origin, link) {
+                    if let Some(parsed_link) = HtmlRecord::check_link(&self.origin, link) {
                         if HtmlRecord::is_host_related(&self.origin, &parsed_link)
                             && HtmlRecord::is_http(&parsed_link)
                             && !HtmlRecord::has_extension(&parsed_link)
@@ -101,7 +153,7 @@ impl HtmlRecord {
         for element in self.html.select(&selector) {
             match element.value().attr("href") {
                 Some(link) => {
-                    if let Ok(link) = HtmlRecord::check_link(&self.origin, link) {
+                    if let Some(link) = HtmlRecord::check_link(&self.origin, link) {
                         if !HtmlRecord::is_host_related(&self.origin, &link)
                             && HtmlRecord::is_http(&link)
                             && !HtmlRecord::has_extension(&link)
@@ -133,7 +185,7 @@ impl HtmlRecord {
         for element in self.html.select(&selector) {
             match element.value().attr("href") {
                 Some(link) => {
-                    if let Ok(parsed_link) = HtmlRecord::check_link(&self.origin, link) {
+                    if let Some(parsed_link) = HtmlRecord::check_link(&self.origin, link) {
                         if re.is_match(&parsed_link) && !HtmlRecord::has_extension(&parsed_link) {
                             ret_vec.push(parsed_link);
                         }
@@ -152,11 +204,12 @@ impl HtmlRecord {
     }
 
     ///public method,
-    /// gets the text of a tag.
-    pub fn tag_text(&self, tag: &str) -> Option<Vec<String>> {
+    /// gets the text of a tag, entities decoded by the html parser.
+    /// errors if tag is not a valid css selector.
+    pub fn tag_text(&self, tag: &str) -> Result<Option<Vec<String>>, Error> {
         let mut ret_vec: Vec<String> = vec![];
         let re = Regex::new(r"\n|\t").unwrap();
-        let selector = Selector::parse(tag).unwrap();
+        let selector = HtmlRecord::selector(tag)?;
         for element in self.html.select(&selector) {
             let text_raw = element.text().collect::<String>(); //Vec<_>>();
             let text_parsed = re.replace_all(&text_raw, "").to_string();
@@ -165,24 +218,25 @@ impl HtmlRecord {
             }
         }
         if ret_vec.is_empty() {
-            None
+            Ok(None)
         } else {
-            Some(ret_vec)
+            Ok(Some(ret_vec))
         }
     }
 
     ///public method,
     /// gets the html of a tag.
-    pub fn tag_html(&self, tag: &str) -> Option<Vec<String>> {
+    /// errors if tag is not a valid css selector.
+    pub fn tag_html(&self, tag: &str) -> Result<Option<Vec<String>>, Error> {
         let mut ret_vec: Vec<String> = vec![];
-        let selector = Selector::parse(tag).unwrap();
+        let selector = HtmlRecord::selector(tag)?;
         for element in self.html.select(&selector) {
             ret_vec.push(element.html());
         }
         if !ret_vec.is_empty() {
-            Some(ret_vec)
+            Ok(Some(ret_vec))
         } else {
-            None
+            Ok(None)
         }
     }
 
@@ -217,8 +271,9 @@ impl HtmlRecord {
                Regex::new(r"([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})").unwrap();
         }
         // iterate over all matches
+        let text = decode_entities(&self.body);
         let emails: HashSet<String> = RE
-            .find_iter(&self.body)
+            .find_iter(&text)
             // try to parse the string matches as i64 (inferred from fn type signature)
             // and filter out the matches that can't be parsed (e.g. if there are too many digits to store in an i64).
             .map(|email| String::from(email.as_str()))
@@ -242,8 +297,9 @@ impl HtmlRecord {
         }
 
         // iterate over all matches
+        let text = decode_entities(&self.body);
         let numbers: HashSet<String> = RE2
-            .find_iter(&self.body)
+            .find_iter(&text)
             // try to parse the string matches as i64 (inferred from fn type signature)
             // and filter out the matches that can't be parsed (e.g. if there are too many digits to store in an i64).
             .map(|number| String::from(number.as_str()))
@@ -259,58 +315,55 @@ impl HtmlRecord {
 
     //the tuple returns the unparsed string in the 0's spot
     //returns the parsed link in the 1's spot
-    pub fn get_image_links(&self) -> Option<HashSet<(String, String)>> {
-        lazy_static! {
-            static ref RE3: Regex = Regex::new(r";base64,").unwrap();
-        }
+    //looks at every place in IMAGE_ATTRIBUTES, srcset values give one entry per candidate
+    //in the order they were found, each pair once (so local names come out the same every run)
+    pub fn get_image_links(&self) -> Option<Vec<(String, String)>> {
         let mut ret_vec: Vec<(String, String)> = vec![];
-        let selector = Selector::parse("img").unwrap();
-        for element in self.html.select(&selector) {
-            match element.value().attr("src") {
-                Some(link) => {
-                    if Url::parse(link) == Err(ParseError::RelativeUrlWithoutBase) {
-                        let base = Url::parse(&self.origin)
-                            .expect("get css links, origin could not be parsed");
-                        let plink = base
-                            .join(link)
-                            .expect("css links, could not join")
-                            .to_string();
-                        ret_vec.push((link.to_string(), plink.to_string()))
-                    } else if RE3.is_match(link) {
+        for (tag, attribute, is_srcset) in IMAGE_ATTRIBUTES {
+            let selector = Selector::parse(tag).unwrap();
+            for element in self.html.select(&selector) {
+                //local name match so svg's xlink:href is found as "href"
+                for (name, value) in element.value().attrs() {
+                    if name != attribute {
                         continue;
-                    } else if let Ok(parsed_link) = Url::parse(link) {
-                        ret_vec.push((link.to_string(), parsed_link.to_string()));
+                    }
+                    let links = if is_srcset {
+                        srcset_candidates(value)
+                            .into_iter()
+                            .map(|(url, _)| url)
+                            .collect()
+                    } else {
+                        vec![value.trim().to_string()]
+                    };
+                    for link in links {
+                        if let Some(plink) = self.resolve_asset_link(&link) {
+                            ret_vec.push((link, plink));
+                        }
                     }
                 }
-                None => continue,
-            };
+            }
         }
 
-        let link_hashset: HashSet<(String, String)> = ret_vec.iter().cloned().collect();
-
-        if link_hashset.is_empty() {
-            None
-        } else {
-            Some(link_hashset)
-        }
+        first_seen(ret_vec)
     }
 
-    pub fn get_css_links(&self) -> Option<HashSet<(String, String)>> {
+    pub fn get_css_links(&self) -> Option<Vec<(String, String)>> {
         let mut ret_vec: Vec<(String, String)> = vec![];
         let selector = Selector::parse("link").unwrap();
         for element in self.html.select(&selector) {
-            if element.value().attr("rel").unwrap() == "stylesheet" {
-                match element.value().attr("href") {
+            let is_stylesheet = element.value().attr("rel").is_some_and(|rel| {
+                rel.split_ascii_whitespace()
+                    .any(|token| token.eq_ignore_ascii_case("stylesheet"))
+            });
+            if is_stylesheet {
+                //trimmed, DomRewriter looks the attribute up trimmed too
+                match element.value().attr("href").map(str::trim) {
                     Some(link) => {
                         //take care of relative links here
                         if Url::parse(link) == Err(ParseError::RelativeUrlWithoutBase) {
-                            let base = Url::parse(&self.origin)
-                                .expect("get css links, origin could not be parsed");
-                            let plink = base
-                                .join(link)
-                                .expect("css links, could not join")
-                                .to_string();
-                            ret_vec.push((link.to_string(), plink.to_string()))
+                            if let Some(plink) = HtmlRecord::join_link(&self.origin, link) {
+                                ret_vec.push((link.to_string(), plink))
+                            }
                         } else if let Ok(parsed_link) = Url::parse(link) {
                             ret_vec.push((link.to_string(), parsed_link.to_string()));
                         }
@@ -320,29 +373,19 @@ impl HtmlRecord {
             }
         }
 
-        let link_hashset: HashSet<(String, String)> = ret_vec.iter().cloned().collect();
-
-        if link_hashset.is_empty() {
-            None
-        } else {
-            Some(link_hashset)
-        }
+        first_seen(ret_vec)
     }
 
-    pub fn get_js_links(&self) -> Option<HashSet<(String, String)>> {
+    pub fn get_js_links(&self) -> Option<Vec<(String, String)>> {
         let mut ret_vec: Vec<(String, String)> = vec![];
         let selector = Selector::parse("script").unwrap();
         for element in self.html.select(&selector) {
-            match element.value().attr("src") {
+            match element.value().attr("src").map(str::trim) {
                 Some(link) => {
                     if Url::parse(link) == Err(ParseError::RelativeUrlWithoutBase) {
-                        let base = Url::parse(&self.origin)
-                            .expect("get js links, origin could not be parsed ");
-                        let plink = base
-                            .join(link)
-                            .expect("js links, could not join")
-                            .to_string();
-                        ret_vec.push((link.to_string(), plink.to_string()))
+                        if let Some(plink) = HtmlRecord::join_link(&self.origin, link) {
+                            ret_vec.push((link.to_string(), plink))
+                        }
                     } else if let Ok(parsed_link) = Url::parse(link) {
                         ret_vec.push((link.to_string(), parsed_link.to_string()));
                     }
@@ -351,12 +394,51 @@ impl HtmlRecord {
             };
         }
 
-        let link_hashset: HashSet<(String, String)> = ret_vec.iter().cloned().collect();
+        first_seen(ret_vec)
+    }
 
-        if link_hashset.is_empty() {
+    ///public method,
+    /// everything in RESOURCE_ATTRIBUTES: icons, manifests, preloads,
+    /// audio/video/track media and object/embed data.
+    /// same (unparsed, parsed) pair as the other link getters plus what kind it is.
+    pub fn get_resource_links(&self) -> Option<Vec<(String, String, ResourceKind)>> {
+        let mut ret_vec: Vec<(String, String, ResourceKind)> = vec![];
+        for (tag, attribute, kind) in RESOURCE_ATTRIBUTES {
+            let selector = Selector::parse(tag).unwrap();
+            for element in self.html.select(&selector) {
+                if let Some(link) = element.value().attr(attribute) {
+                    if let Some(plink) = self.resolve_asset_link(link.trim()) {
+                        ret_vec.push((link.trim().to_string(), plink, kind));
+                    }
+                }
+            }
+        }
+
+        first_seen(ret_vec)
+    }
+
+    ///public method,
+    /// returns the contents of every <style> block and style="" attribute,
+    /// used to find the url()s that live in the page itself.
+    pub fn get_inline_styles(&self) -> Option<Vec<String>> {
+        let mut ret_vec: Vec<String> = vec![];
+        let style_selector = Selector::parse("style").unwrap();
+        for element in self.html.select(&style_selector) {
+            ret_vec.push(element.text().collect::<String>());
+        }
+        let attr_selector = Selector::parse("[style]").unwrap();
+        for element in self.html.select(&attr_selector) {
+            //trimmed like the other attributes, DomRewriter::rewrite_styles looks it up that way
+            if let Some(style) = element.value().attr("style") {
+                ret_vec.push(style.trim().to_string());
+            }
+        }
+        ret_vec.retain(|style| !style.trim().is_empty());
+
+        if ret_vec.is_empty() {
             None
         } else {
-            Some(link_hashset)
+            Some(ret_vec)
         }
     }
 
@@ -374,10 +456,10 @@ impl HtmlRecord {
     /// is associated with the domain.
     /// needs refactoring.
     fn is_host_related(check_string: &str, link: &str) -> bool {
-        let origin_url = Url::parse(check_string).expect("self is not a url");
-        let url_to_check = Url::parse(link).expect("link is not a string");
-
-        origin_url.host() == url_to_check.host()
+        match (Url::parse(check_string), Url::parse(link)) {
+            (Ok(origin_url), Ok(url_to_check)) => origin_url.host() == url_to_check.host(),
+            _ => false,
+        }
     }
 
     ///private method: check_link
@@ -385,9 +467,9 @@ impl HtmlRecord {
     /// input "in_link", type &str, stands for an HtmlDocument anchor that may need parsing
     /// this is a cursory check to see if a parse is needed
     /// returns string
-    fn check_link(origin: &str, in_link: &str) -> Result<String, std::string::ParseError> {
+    fn check_link(origin: &str, in_link: &str) -> Option<String> {
         match Url::parse(in_link) {
-            Ok(link) => Ok(link.to_string()),
+            Ok(link) => Some(link.to_string()),
             Err(_) => HtmlRecord::parse_link(origin, in_link),
         }
     }
@@ -397,9 +479,9 @@ impl HtmlRecord {
     /// the output is the parsed string
     /// use case
     /// get an anchor tag href where it is unparsed, as in "/"
-    fn parse_link(origin: &str, unparsed_link: &str) -> Result<String, std::string::ParseError> {
-        let host = Url::parse(origin).expect("origin not a url");
-        let host_string = host.host_str().expect("host is not doable");
+    fn parse_link(origin: &str, unparsed_link: &str) -> Option<String> {
+        let host = Url::parse(origin).ok()?;
+        let host_string = host.host_str()?;
 
         let parsed_link = if unparsed_link.substring(0, 1) == "/" {
             format!("{}://{}{}", host.scheme(), host_string, unparsed_link)
@@ -407,7 +489,7 @@ impl HtmlRecord {
             format!("{}://{}/{}", host.scheme(), host_string, unparsed_link)
         };
 
-        Ok(parsed_link)
+        Some(parsed_link)
     }
 
     /// private method
@@ -415,16 +497,216 @@ impl HtmlRecord {
     /// use case:
     /// this is used as conditional method to whether its an actual http url
     fn is_http(link: &str) -> bool {
-        let url = Url::parse(link).expect("is http failed");
-
-        url.scheme() == "http" || url.scheme() == "https"
+        match Url::parse(link) {
+            Ok(url) => url.scheme() == "http" || url.scheme() == "https",
+            Err(_) => false,
+        }
     }
 
     fn has_extension(link: &str) -> bool {
-        let url = Url::parse(link).expect("is http failed");
-        let extention_vec = Vec::from_iter(url.path().split('.'));
+        match Url::parse(link) {
+            Ok(url) => match url.path().rsplit('.').next() {
+                Some(extention) => EXTENTIONS.contains(&extention),
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// private method
+    /// absolute url for an asset reference, none for data: uris,
+    /// empty values and anything that can't be parsed.
+    fn resolve_asset_link(&self, link: &str) -> Option<String> {
+        if link.is_empty() || link.starts_with('#') || link.to_lowercase().starts_with("data:") {
+            return None;
+        }
+        match Url::parse(link) {
+            Err(ParseError::RelativeUrlWithoutBase) => HtmlRecord::join_link(&self.origin, link),
+            Ok(parsed_link) => Some(parsed_link.to_string()),
+            Err(_) => None,
+        }
+    }
+
+    /// private method
+    /// joins a relative link onto the origin, none if either can't be parsed.
+    fn join_link(origin: &str, link: &str) -> Option<String> {
+        let base = Url::parse(origin).ok()?;
+        Some(base.join(link).ok()?.to_string())
+    }
+
+    /// private method
+    /// parses a user supplied css selector.
+    pub(crate) fn selector(tag: &str) -> Result<Selector, Error> {
+        Selector::parse(tag).map_err(|e| Error::Parse(format!("selector {}: {:?}", tag, e)))
+    }
+}
+
+///what an archived resource is, decides the snapshot subdirectory it goes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Icon,
+    Manifest,
+    Font,
+    Image,
+    Style,
+    Script,
+    Media,
+    Track,
+    Object,
+}
 
-        EXTENTIONS.contains(&extention_vec[extention_vec.len() - 1])
+impl ResourceKind {
+    pub fn directory(&self) -> &'static str {
+        match self {
+            ResourceKind::Icon => "icons",
+            ResourceKind::Manifest => "manifest",
+            ResourceKind::Font => "fonts",
+            ResourceKind::Image => "images",
+            ResourceKind::Style => "css",
+            ResourceKind::Script => "js",
+            ResourceKind::Media => "media",
+            ResourceKind::Track => "tracks",
+            ResourceKind::Object => "objects",
+        }
     }
 }
+
+///(selector, attribute, kind) for the link-rel, media and embedded resources.
+/// images, stylesheets and scripts have their own getters.
+pub static RESOURCE_ATTRIBUTES: [(&str, &str, ResourceKind); 21] = [
+    ("link[rel~=icon]", "href", ResourceKind::Icon),
+    ("link[rel~=apple-touch-icon]", "href", ResourceKind::Icon),
+    (
+        "link[rel~=apple-touch-icon-precomposed]",
+        "href",
+        ResourceKind::Icon,
+    ),
+    ("link[rel~=mask-icon]", "href", ResourceKind::Icon),
+    ("link[rel~=manifest]", "href", ResourceKind::Manifest),
+    ("link[rel~=preload][as=font]", "href", ResourceKind::Font),
+    ("link[rel~=preload][as=image]", "href", ResourceKind::Image),
+    ("link[rel~=preload][as=style]", "href", ResourceKind::Style),
+    (
+        "link[rel~=preload][as=script]",
+        "href",
+        ResourceKind::Script,
+    ),
+    ("link[rel~=preload][as=audio]", "href", ResourceKind::Media),
+    ("link[rel~=preload][as=video]", "href", ResourceKind::Media),
+    ("link[rel~=preload][as=track]", "href", ResourceKind::Track),
+    ("link[rel~=modulepreload]", "href", ResourceKind::Script),
+    ("audio", "src", ResourceKind::Media),
+    ("video", "src", ResourceKind::Media),
+    ("audio source", "src", ResourceKind::Media),
+    ("video source", "src", ResourceKind::Media),
+    ("track", "src", ResourceKind::Track),
+    ("object", "data", ResourceKind::Object),
+    ("embed", "src", ResourceKind::Object),
+    ("link[rel~=prefetch][as=font]", "href", ResourceKind::Font),
+];
+
+///(selector, attribute, is a srcset) for every place an image url can live,
+/// lazy loaders keep the real url in data-src/data-srcset until scripts run.
+pub static IMAGE_ATTRIBUTES: [(&str, &str, bool); 12] = [
+    ("img", "src", false),
+    ("img", "srcset", true),
+    ("img", "data-src", false),
+    ("img", "data-srcset", true),
+    ("img", "data-lazy-src", false),
+    ("picture source", "srcset", true),
+    ("picture source", "data-srcset", true),
+    ("video", "poster", false),
+    ("input[type=image]", "src", false),
+    ("image", "href", false),
+    ("[data-bg]", "data-bg", false),
+    ("[data-background-image]", "data-background-image", false),
+];
+
 pub static EXTENTIONS: [&str; 7] = ["jpeg", "jpg", "css", "js", "webm", "webp", "png"];
+
+///public function:
+/// splits a srcset value into (url, descriptor) pairs,
+/// "a.png 1x, b.png 2x" -> [("a.png", "1x"), ("b.png", "2x")]
+pub fn srcset_candidates(srcset: &str) -> Vec<(String, String)> {
+    let mut candidates: Vec<(String, String)> = vec![];
+    let mut rest = srcset.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    while !rest.is_empty() {
+        let url_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let mut url = &rest[..url_end];
+        rest = &rest[url_end..];
+
+        let mut descriptor = "";
+        if url.ends_with(',') {
+            url = url.trim_end_matches(',');
+        } else {
+            let descriptor_end = rest.find(',').unwrap_or(rest.len());
+            descriptor = rest[..descriptor_end].trim();
+            rest = &rest[descriptor_end..];
+        }
+        if !url.is_empty() {
+            candidates.push((url.to_string(), descriptor.to_string()));
+        }
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    candidates
+}
+
+///public function:
+/// decodes character references the way a browser does in text
+/// (named, numeric, legacy ones without the semicolon, windows-1252 remaps).
+/// runs the html5ever tokenizer in rcdata mode so markup stays as plain text,
+/// "&lt;b&gt; &amp;amp; &#x263a;" -> "<b> &amp; \u{263a}"
+pub fn decode_entities(text: &str) -> String {
+    let mut tokenizer = Tokenizer::new(
+        TextSink(String::with_capacity(text.len())),
+        TokenizerOpts {
+            initial_state: Some(states::RawData(states::Rcdata)),
+            ..Default::default()
+        },
+    );
+    let mut queue = BufferQueue::new();
+    queue.push_back(StrTendril::from_slice(text));
+    let _ = tokenizer.feed(&mut queue);
+    tokenizer.end();
+    tokenizer.sink.0
+}
+
+///collects the character tokens, there are no others in rcdata
+/// since no end tag can match.
+struct TextSink(String);
+
+impl TokenSink for TextSink {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::CharacterTokens(text) => self.0.push_str(&text),
+            Token::NullCharacterToken => self.0.push('\u{fffd}'),
+            _ => {}
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+///8 random lowercase letters and digits.
+fn new_snapshot_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect()
+}
+
+///the links in the order they were found, each only once, none when there are none.
+fn first_seen<T: Eq + Hash + Clone>(links: Vec<T>) -> Option<Vec<T>> {
+    let mut seen: HashSet<T> = HashSet::new();
+    let links: Vec<T> = links
+        .into_iter()
+        .filter(|link| seen.insert(link.clone()))
+        .collect();
+    if links.is_empty() {
+        None
+    } else {
+        Some(links)
+    }
+}
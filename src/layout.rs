@@ -0,0 +1,103 @@
+use crate::error::Error;
+use crate::html::HtmlRecord;
+use crate::sanitize::sanitize_component;
+use crate::snapshot::sha256_hex;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use std::fmt;
+use url::Url;
+
+///decides where under the base path a snapshot goes.
+/// implement it and set ArchiverConfig::layout to archive into your own tree,
+/// the returned path is relative and uses '/' as separator.
+pub trait SnapshotLayout: fmt::Debug + Send + Sync {
+    fn relative_directory(&self, html_record: &HtmlRecord) -> Result<String, Error>;
+}
+
+///the default layout:
+/// host[_port]/path/[_q_{query hash}/]{YYYYMMDDhhmmss}_{snapshot id}
+/// "https://example.com:8080/news/?page=2" ->
+/// "example.com_8080/news/_q_{12 hex digits}/20240101000000_{snapshot id}"
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostPathLayout;
+
+impl SnapshotLayout for HostPathLayout {
+    fn relative_directory(&self, html_record: &HtmlRecord) -> Result<String, Error> {
+        let url = Url::parse(&html_record.origin)?;
+        let mut directory = host_directory(&url)?;
+
+        for segment in url.path_segments().into_iter().flatten() {
+            if !segment.is_empty() {
+                directory.push('/');
+                directory.push_str(&sanitize_component(segment));
+            }
+        }
+        if let Some(query) = url.query() {
+            directory.push_str(&format!("/_q_{}", query_hash(query)));
+        }
+        directory.push_str(&format!(
+            "/{}_{}",
+            wayback_timestamp(&html_record.date_time),
+            html_record.snapshot_id
+        ));
+        Ok(directory)
+    }
+}
+
+///every page of a host side by side, named by capture:
+/// host[_port]/{YYYYMMDDhhmmss}_{snapshot id}
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlatLayout;
+
+impl SnapshotLayout for FlatLayout {
+    fn relative_directory(&self, html_record: &HtmlRecord) -> Result<String, Error> {
+        let url = Url::parse(&html_record.origin)?;
+        Ok(format!(
+            "{}/{}_{}",
+            host_directory(&url)?,
+            wayback_timestamp(&html_record.date_time),
+            html_record.snapshot_id
+        ))
+    }
+}
+
+///public function:
+/// "example.com", or "example.com_8080" when the url has a non default port.
+pub fn host_directory(url: &Url) -> Result<String, Error> {
+    let host = match url.host_str() {
+        Some(host) => sanitize_component(
+            &host
+                .trim_matches(|c| c == '[' || c == ']')
+                .replace(':', "-"),
+        ),
+        None => return Err(Error::InvalidUrl(format!("no host in {}", url))),
+    };
+    Ok(match url.port() {
+        Some(port) => format!("{}_{}", host, port),
+        None => host,
+    })
+}
+
+///short stable name for a query string, the first 12 hex digits of its sha-256.
+pub fn query_hash(query: &str) -> String {
+    sha256_hex(query.as_bytes())[..12].to_string()
+}
+
+///public function:
+/// 14 digit wayback style timestamp, YYYYMMDDhhmmss in utc.
+/// sorts chronologically as plain text and is safe in paths and urls.
+pub fn wayback_timestamp(date_time: &DateTime<Utc>) -> String {
+    date_time.format("%Y%m%d%H%M%S").to_string()
+}
+
+///public function:
+/// reads the capture time back from a snapshot directory name
+/// ("20240101000000_k3j9x0qa" or just "20240101000000").
+pub fn parse_wayback_timestamp(name: &str) -> Result<DateTime<Utc>, Error> {
+    let digits = name.split('_').next().unwrap_or(name);
+    if digits.len() != 14 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Parse(format!("not a 14 digit timestamp: {}", name)));
+    }
+    NaiveDateTime::parse_from_str(digits, "%Y%m%d%H%M%S")
+        .map(|naive| Utc.from_utc_datetime(&naive))
+        .map_err(|e| Error::Parse(format!("{}: {}", name, e)))
+}
@@ -1,8 +1,27 @@
-mod client;
+pub mod charset;
+pub mod client;
 pub mod crawler;
+pub mod css;
+pub mod error;
+pub mod fetcher;
 pub mod html;
+pub mod layout;
+pub mod mhtml;
+pub mod mime;
+pub mod objects;
+pub mod print;
+pub mod render;
+pub mod rewrite;
+pub mod sanitize;
+pub mod screenshot;
+pub mod single_file;
+pub mod snapshot;
+pub mod wait;
+pub mod warc;
 pub mod web_archiver;
 
+pub use error::Error;
+
 //archiver tests
 #[cfg(test)]
 mod tests {
@@ -10,7 +29,6 @@ mod tests {
         crawler::{BasicCrawler, FantocciniCrawler},
         web_archiver::{BasicArchiver, FantocciniArchiver},
     };
-    use dirs;
 
     macro_rules! aw {
         ($e:expr) => {
@@ -28,14 +46,13 @@ mod tests {
             let archiver = FantocciniArchiver::new(connection_string).await.unwrap();
 
             match archiver.create_archive(url, &new_dir).await {
-                Ok(path) => {
+                Ok(result) => {
                     let _ = archiver.close().await;
-                    assert!(path.len() > 0)
+                    assert!(result.index_path().is_some_and(|path| !path.is_empty()))
                 }
                 Err(e) => {
                     let _ = archiver.close().await;
-                    println!("{:?}", e);
-                    assert!(false);
+                    panic!("{:?}", e);
                 }
             }
         });
@@ -55,7 +72,7 @@ mod tests {
             let paths = archiver.create_archives(urls, &new_dir).await.unwrap();
             let _ = archiver.close().await;
 
-            assert!(paths.len() > 0);
+            assert!(!paths.is_empty());
         });
     }
 
@@ -66,7 +83,10 @@ mod tests {
             let home_dir = dirs::home_dir().expect("Failed to get home directory");
             let new_dir = format!("{}{}", home_dir.to_str().unwrap(), "/Projects/archive_test");
             println!("{:?}", new_dir);
-            assert!(BasicArchiver::create_archive(url, &new_dir).await.is_ok());
+            assert!(BasicArchiver::default()
+                .create_archive(url, &new_dir)
+                .await
+                .is_ok());
         });
     }
 
@@ -92,10 +112,1616 @@ mod tests {
             let url = "https://www.rust-lang.org/";
             let home_dir = dirs::home_dir().expect("Failed to get home directory");
             let new_dir = format!("{}{}", home_dir.to_str().unwrap(), "/Projects/archive_test");
-            let paths = BasicCrawler::save_crawl(url, &new_dir, 2).await.unwrap();
+            let paths = BasicCrawler::default()
+                .save_crawl(url, &new_dir, 2)
+                .await
+                .unwrap();
 
             println!("{:?}", paths);
             assert!(paths.len() == 2);
         });
     }
+
+    #[test]
+    fn warc_records() {
+        use crate::warc::WarcWriter;
+        use flate2::read::MultiGzDecoder;
+        use std::io::Read;
+
+        let path = std::env::temp_dir().join("lolchive_warc_records.warc.gz");
+        let mut writer = WarcWriter::create(path.to_str().unwrap()).unwrap();
+        let id = writer
+            .write_resource(
+                "https://example.com/",
+                "text/html",
+                b"<html></html>".to_vec(),
+            )
+            .unwrap();
+        writer
+            .write_metadata("https://example.com/", &id, vec![])
+            .unwrap();
+        drop(writer);
+
+        let mut warc = String::new();
+        MultiGzDecoder::new(std::fs::File::open(&path).unwrap())
+            .read_to_string(&mut warc)
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(warc.matches("WARC/1.1\r\n").count(), 3);
+        assert!(warc.contains("WARC-Type: warcinfo\r\n"));
+        assert!(warc.contains("Content-Length: 13\r\n\r\n<html></html>\r\n\r\n"));
+        assert!(warc.contains(&format!("WARC-Concurrent-To: {}\r\n", id)));
+    }
+
+    #[test]
+    fn bad_selector_is_an_error() {
+        use crate::{html::HtmlRecord, Error};
+
+        let record = HtmlRecord::new(
+            "https://example.com/".to_string(),
+            "<p>one</p><p>two</p>".to_string(),
+        );
+        assert_eq!(
+            record.tag_text("p").unwrap(),
+            Some(vec!["one".to_string(), "two".to_string()])
+        );
+        assert!(matches!(
+            record.tag_text("p[").unwrap_err(),
+            Error::Parse(_)
+        ));
+    }
+
+    #[test]
+    fn css_url_rewrite() {
+        use crate::css::{css_references, rewrite_css};
+        use std::collections::HashMap;
+
+        let css = r#"@import "base.css";
+@import url(print.css) print;
+body { background: url('/img/bg.png') }
+.icon { background: url(data:image/png;base64,AAAA) }
+@font-face { src: url("fonts/a.woff2?v=1") format("woff2") }"#;
+
+        let references = css_references(css);
+        let urls: Vec<(&str, bool)> = references
+            .iter()
+            .map(|r| (r.url.as_str(), r.import))
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                ("base.css", true),
+                ("print.css", true),
+                ("/img/bg.png", false),
+                ("fonts/a.woff2?v=1", false)
+            ]
+        );
+
+        let mut replacements = HashMap::new();
+        replacements.insert("/img/bg.png".to_string(), "../images/bg.png".to_string());
+        let rewritten = rewrite_css(css, &replacements);
+        assert!(rewritten.contains("url('../images/bg.png')"));
+        assert!(rewritten.contains("@import \"base.css\";"));
+    }
+
+    #[test]
+    fn dom_rewrite_only_touches_attributes() {
+        use crate::{html::HtmlRecord, rewrite::DomRewriter};
+        use std::collections::HashMap;
+
+        let body = r#"<html><head><script>var a = "/a.png";</script></head><body><img src="/a.png" srcset="/a.png 1x, /b.png 2x"><img src="/a.png.bak"><p>/a.png</p></body></html>"#;
+        let record = HtmlRecord::new("https://example.com/".to_string(), body.to_string());
+        let mut replacements = HashMap::new();
+        replacements.insert("/a.png".to_string(), "./images/a.png".to_string());
+
+        let mut rewriter = DomRewriter::new(&record);
+        rewriter
+            .rewrite_attribute("img", "src", &replacements)
+            .unwrap();
+        rewriter
+            .rewrite_srcset("img", "srcset", &replacements)
+            .unwrap();
+        let html = rewriter.html();
+
+        assert!(
+            html.contains(r#"<img src="./images/a.png" srcset="./images/a.png 1x, /b.png 2x">"#)
+        );
+        assert!(html.contains(r#"<img src="/a.png.bak">"#));
+        assert!(html.contains(r#"var a = "/a.png";"#));
+        assert!(html.contains("<p>/a.png</p>"));
+    }
+
+    #[test]
+    fn image_extension_from_bytes() {
+        use crate::mime::image_extension;
+
+        assert_eq!(image_extension(None, b"\x89PNG\r\n\x1a\n...."), Some("png"));
+        assert_eq!(
+            image_extension(Some("image/png"), b"GIF89a...."),
+            Some("gif")
+        );
+        assert_eq!(image_extension(None, b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(
+            image_extension(
+                None,
+                b"<?xml version=\"1.0\"?><svg xmlns=\"http://www.w3.org/2000/svg\"/>"
+            ),
+            Some("svg")
+        );
+        assert_eq!(image_extension(Some("image/avif"), b"????"), Some("avif"));
+        assert_eq!(image_extension(Some("text/plain"), b"????"), None);
+    }
+
+    #[test]
+    fn responsive_and_lazy_images() {
+        use crate::{html::HtmlRecord, rewrite::DomRewriter};
+        use std::collections::HashMap;
+
+        let body = r#"<picture><source srcset="/w.webp 1x, /w2.webp 2x"><img src="data:image/gif;base64,R0lG" data-src="/lazy.jpg" loading="lazy"></picture>
+<video poster="poster.png"></video><input type="image" src="/go.png">
+<svg><image xlink:href="/vector.png"></image></svg>"#;
+        let record = HtmlRecord::new("https://example.com/a/".to_string(), body.to_string());
+        let mut links: Vec<String> = record
+            .get_image_links()
+            .unwrap()
+            .into_iter()
+            .map(|link| link.1)
+            .collect();
+        links.sort();
+        assert_eq!(
+            links,
+            vec![
+                "https://example.com/a/poster.png",
+                "https://example.com/go.png",
+                "https://example.com/lazy.jpg",
+                "https://example.com/vector.png",
+                "https://example.com/w.webp",
+                "https://example.com/w2.webp",
+            ]
+        );
+
+        let mut replacements = HashMap::new();
+        replacements.insert("/lazy.jpg".to_string(), "./images/lazy.jpg".to_string());
+        let mut rewriter = DomRewriter::new(&record);
+        rewriter.promote_lazy(&replacements).unwrap();
+        assert!(rewriter.html().contains(r#"src="./images/lazy.jpg""#));
+    }
+
+    #[test]
+    fn link_rel_and_media_resources() {
+        use crate::html::{HtmlRecord, ResourceKind};
+
+        let body = r#"<head><link href="/no-rel.css"><link rel="shortcut icon" href="/favicon.ico">
+<link rel="manifest" href="/site.webmanifest"><link rel="preload" as="font" href="/f.woff2" crossorigin>
+<link rel="modulepreload" href="/app.mjs"></head>
+<body><video src="/clip.mp4"><track src="/subs.vtt"></video><object data="/doc.pdf"></object></body>"#;
+        let record = HtmlRecord::new("https://example.com/".to_string(), body.to_string());
+        assert!(record.get_css_links().is_none());
+
+        let mut resources: Vec<(String, ResourceKind)> = record
+            .get_resource_links()
+            .unwrap()
+            .into_iter()
+            .map(|(raw, _, kind)| (raw, kind))
+            .collect();
+        resources.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            resources,
+            vec![
+                ("/app.mjs".to_string(), ResourceKind::Script),
+                ("/clip.mp4".to_string(), ResourceKind::Media),
+                ("/doc.pdf".to_string(), ResourceKind::Object),
+                ("/f.woff2".to_string(), ResourceKind::Font),
+                ("/favicon.ico".to_string(), ResourceKind::Icon),
+                ("/site.webmanifest".to_string(), ResourceKind::Manifest),
+                ("/subs.vtt".to_string(), ResourceKind::Track),
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_stylesheets_and_scripts() {
+        use crate::{html::HtmlRecord, rewrite::DomRewriter, single_file::data_uri};
+        use std::collections::HashMap;
+
+        let body = r#"<html><head><link rel="stylesheet" href="/a.css" media="print"><link rel="stylesheet" href="/missing.css"></head><body><script src="/a.js" defer></script></body></html>"#;
+        let record = HtmlRecord::new("https://example.com/".to_string(), body.to_string());
+        let mut rewriter = DomRewriter::new(&record);
+        let css = HashMap::from([("/a.css".to_string(), "p{color:red}</STYLE>".to_string())]);
+        let js = HashMap::from([(
+            "/a.js".to_string(),
+            "document.write('</script>')".to_string(),
+        )]);
+        rewriter.inline_stylesheets(&css).unwrap();
+        rewriter.inline_scripts(&js).unwrap();
+        let html = rewriter.html();
+
+        assert!(html.contains(r#"<style media="print">p{color:red}<\/STYLE></style>"#));
+        assert!(html.contains(r#"<link rel="stylesheet" href="/missing.css">"#));
+        assert!(html.contains(r#"<script>document.write('<\/script>')</script>"#));
+        assert_eq!(data_uri("text/css", b"p{}"), "data:text/css;base64,cHt9");
+    }
+
+    #[test]
+    fn mhtml_parts() {
+        use crate::mhtml::{quoted_printable, MhtmlWriter};
+
+        let mut writer = MhtmlWriter::new();
+        writer.subject = Some("caf\u{e9}".to_string());
+        writer.add_part(
+            "https://example.com/",
+            "text/html; charset=utf-8",
+            b"<p>a=b</p> \n".to_vec(),
+        );
+        writer.add_part("https://example.com/a.png", "image/png", vec![0x89, 0x50]);
+        writer.add_part("https://example.com/a.png", "image/png", vec![0]);
+        assert_eq!(writer.parts().len(), 2);
+
+        let document = String::from_utf8(writer.to_bytes()).unwrap();
+        assert!(document.contains("Snapshot-Content-Location: https://example.com/\r\n"));
+        assert!(document.contains("Subject: =?utf-8?B?Y2Fmw6k=?=\r\n"));
+        assert!(document.contains(&format!("boundary=\"{}\"", writer.boundary)));
+        assert!(
+            document.contains("Content-Location: https://example.com/\r\n\r\n<p>a=3Db</p>=20\r\n")
+        );
+        assert!(document.contains("Content-Transfer-Encoding: base64\r\nContent-Location: https://example.com/a.png\r\n\r\niVA=\r\n"));
+        assert!(document.ends_with(&format!("--{}--\r\n", writer.boundary)));
+
+        let long = quoted_printable(&[b'x'; 200]);
+        assert!(long.split("\r\n").all(|line| line.len() <= 76));
+    }
+
+    #[test]
+    fn entities_are_decoded_only_in_text() {
+        use crate::html::{decode_entities, HtmlRecord};
+
+        assert_eq!(
+            decode_entities("&lt;script&gt; &amp;amp; &#x263a; &#128; &copy &apos;x&apos; &notin;"),
+            "<script> &amp; \u{263a} \u{20ac} \u{a9} 'x' \u{2209}"
+        );
+        assert_eq!(decode_entities("a &unknown; b</p>"), "a &unknown; b</p>");
+
+        let body = "<html><head><title>a &lt;script&gt; b</title></head><body><p>&lt;script&gt;alert(1)&lt;/script&gt;</p><a href=\"/x?a=1&amp;b=2\">x</a></body></html>";
+        let record = HtmlRecord::new("https://example.com/".to_string(), body.to_string());
+        assert!(record.tag_html("script").unwrap().is_none());
+        assert_eq!(
+            record.tag_text("p").unwrap(),
+            Some(vec!["<script>alert(1)</script>".to_string()])
+        );
+        assert_eq!(
+            record.tag_text("title").unwrap(),
+            Some(vec!["a <script> b".to_string()])
+        );
+        assert!(record
+            .domain_anchors()
+            .unwrap()
+            .contains("https://example.com/x?a=1&b=2"));
+    }
+
+    #[test]
+    fn charset_detection_and_utf8_output() {
+        use crate::charset::{decode_html, detect_html_encoding};
+        use crate::{html::HtmlRecord, rewrite::DomRewriter};
+
+        //"\u{30c6}\u{30b9}\u{30c8}" in shift_jis
+        let sjis = b"<html><head><meta charset=\"Shift_JIS\"></head><body>\x83\x65\x83\x58\x83\x67</body></html>";
+        assert_eq!(detect_html_encoding(sjis, None).name(), "Shift_JIS");
+        assert!(decode_html(sjis, None).contains("\u{30c6}\u{30b9}\u{30c8}"));
+        //header wins over meta, bom wins over header
+        assert_eq!(
+            detect_html_encoding(sjis, Some("text/html; charset=windows-1251")).name(),
+            "windows-1251"
+        );
+        assert_eq!(
+            detect_html_encoding(b"\xef\xbb\xbf<p>x</p>", Some("text/html; charset=gbk")).name(),
+            "UTF-8"
+        );
+        let http_equiv =
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=gb2312\">";
+        assert_eq!(detect_html_encoding(http_equiv, None).name(), "GBK");
+        assert_eq!(
+            detect_html_encoding(b"caf\xe9", None).name(),
+            "windows-1252"
+        );
+
+        let record = HtmlRecord::new("https://example.com/".to_string(), decode_html(sjis, None));
+        let mut rewriter = DomRewriter::new(&record);
+        rewriter.declare_utf8().unwrap();
+        let html = rewriter.html();
+        assert!(html.contains("<head><meta charset=\"utf-8\"></head>"));
+        assert!(!html.contains("Shift_JIS"));
+    }
+
+    #[test]
+    fn record_metadata() {
+        use crate::html::HtmlRecord;
+        use std::time::Duration;
+
+        let mut record = HtmlRecord::new("http://example.com/".to_string(), "<p>x</p>".to_string());
+        assert_eq!(record.metadata()["status"], serde_json::Value::Null);
+
+        record.status = Some(200);
+        record.headers = vec![
+            ("content-type".to_string(), "text/html".to_string()),
+            ("set-cookie".to_string(), "a=1".to_string()),
+            ("set-cookie".to_string(), "b=2".to_string()),
+        ];
+        record.final_url = Some("https://www.example.com/".to_string());
+        record.redirects = vec![
+            "http://example.com/".to_string(),
+            "https://example.com/".to_string(),
+        ];
+        record.fetch_duration = Some(Duration::from_millis(1500));
+        record.content_length = Some(8);
+
+        let metadata = record.metadata();
+        assert_eq!(metadata["url"], "http://example.com/");
+        assert_eq!(metadata["final_url"], "https://www.example.com/");
+        assert_eq!(metadata["status"], 200);
+        assert_eq!(metadata["redirects"].as_array().unwrap().len(), 2);
+        assert_eq!(metadata["headers"][2][1], "b=2");
+        assert_eq!(metadata["fetch_duration_ms"], 1500);
+        assert_eq!(metadata["content_length"], 8);
+    }
+
+    #[test]
+    fn manifest_entries() {
+        use crate::snapshot::{sha256_hex, Manifest, ResourceEntry, Snapshot};
+        use crate::Error;
+
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let url = "https://example.com/a.png";
+        let not_found = Error::HttpStatus {
+            url: url.to_string(),
+            status: 404,
+        };
+        let mut manifest = Manifest::default();
+        manifest.push(ResourceEntry::failed(url, &not_found));
+        assert_eq!(manifest.resources[0].status, Some(404));
+        assert!(manifest.resources[0].error.is_some());
+
+        manifest.push(ResourceEntry::saved(
+            url,
+            "images/a.png",
+            "image/png",
+            200,
+            b"abc",
+        ));
+        manifest.push(ResourceEntry::failed(url, &not_found));
+        assert_eq!(manifest.resources.len(), 1);
+        assert_eq!(
+            manifest.resources[0].local_path.as_deref(),
+            Some("images/a.png")
+        );
+        assert_eq!(manifest.resources[0].size, Some(3));
+
+        let snapshot = Snapshot {
+            id: "k3j9x0qa".to_string(),
+            url: "https://example.com/".to_string(),
+            date_time: chrono::Utc::now(),
+            directory: String::new(),
+            index_path: String::new(),
+            screenshot_path: None,
+            pdf_path: None,
+            resources: manifest.resources,
+        };
+        assert_eq!(snapshot.saved().count(), 1);
+        assert_eq!(snapshot.failed().count(), 0);
+        let json = snapshot.to_json();
+        assert_eq!(json["resources"][0]["sha256"], sha256_hex(b"abc"));
+        assert_eq!(json["resources"][0]["error"], serde_json::Value::Null);
+        assert_eq!(json["index"], "index.html");
+        assert_eq!(json["screenshot"], serde_json::Value::Null);
+        assert_eq!(json["pdf"], serde_json::Value::Null);
+
+        let snapshot = Snapshot {
+            screenshot_path: Some("/tmp/screenshot.png".to_string()),
+            pdf_path: Some("/tmp/page.pdf".to_string()),
+            ..snapshot
+        };
+        let json = snapshot.to_json();
+        assert_eq!(json["screenshot"], "screenshot.png");
+        assert_eq!(json["pdf"], "page.pdf");
+    }
+
+    #[test]
+    fn archive_results() {
+        use crate::snapshot::{ArchiveResult, ResourceEntry, Snapshot};
+        use crate::Error;
+
+        let snapshot = Snapshot {
+            id: "k3j9x0qa".to_string(),
+            url: "https://example.com/".to_string(),
+            date_time: chrono::Utc::now(),
+            directory: "/tmp/example.com/20240101000000_k3j9x0qa".to_string(),
+            index_path: "/tmp/example.com/20240101000000_k3j9x0qa/index.html".to_string(),
+            screenshot_path: None,
+            pdf_path: None,
+            resources: vec![
+                ResourceEntry::saved(
+                    "https://example.com/a.css",
+                    "css/a.css",
+                    "text/css",
+                    200,
+                    b"p{}",
+                ),
+                ResourceEntry::failed(
+                    "https://example.com/b.png",
+                    &Error::HttpStatus {
+                        url: "https://example.com/b.png".to_string(),
+                        status: 404,
+                    },
+                ),
+            ],
+        };
+        let result = ArchiveResult::archived(
+            "https://example.com/",
+            Some("https://www.example.com/".to_string()),
+            snapshot,
+            vec!["no screenshot".to_string()],
+        );
+        assert!(result.is_ok());
+        assert_eq!(result.assets_saved, 1);
+        assert_eq!(result.assets_failed, 1);
+        assert_eq!(result.warnings.len(), 2);
+        assert!(result.warnings[1].contains("https://example.com/b.png"));
+        assert_eq!(
+            result.index_path(),
+            Some("/tmp/example.com/20240101000000_k3j9x0qa/index.html")
+        );
+
+        let failed = ArchiveResult::failed(
+            "https://example.com/missing",
+            Error::InvalidUrl("no host".to_string()),
+        );
+        assert!(!failed.is_ok());
+        assert!(failed.index_path().is_none());
+    }
+
+    #[test]
+    fn snapshot_layouts() {
+        use crate::html::HtmlRecord;
+        use crate::layout::{query_hash, FlatLayout, HostPathLayout, SnapshotLayout};
+        use crate::Error;
+
+        let record = |url: &str| HtmlRecord::new(url.to_string(), "<p>x</p>".to_string());
+
+        let page_1 = record("https://example.com:8080/news/list?page=1");
+        let page_2 = record("https://example.com:8080/news/list?page=2");
+        let layout_1 = HostPathLayout.relative_directory(&page_1).unwrap();
+        let layout_2 = HostPathLayout.relative_directory(&page_2).unwrap();
+        assert_ne!(layout_1, layout_2);
+        assert_eq!(
+            layout_1,
+            format!(
+                "example.com_8080/news/list/_q_{}/{}_{}",
+                query_hash("page=1"),
+                crate::layout::wayback_timestamp(&page_1.date_time),
+                page_1.snapshot_id
+            )
+        );
+
+        //same url, same second, still two directories
+        let first = record("https://example.com/");
+        let second = record("https://example.com/");
+        assert_ne!(first.snapshot_id, second.snapshot_id);
+        assert_ne!(
+            HostPathLayout.relative_directory(&first).unwrap(),
+            HostPathLayout.relative_directory(&second).unwrap()
+        );
+        assert!(FlatLayout
+            .relative_directory(&first)
+            .unwrap()
+            .starts_with("example.com/"));
+
+        #[derive(Debug)]
+        struct ById;
+        impl SnapshotLayout for ById {
+            fn relative_directory(&self, html_record: &HtmlRecord) -> Result<String, Error> {
+                Ok(format!("by-id/{}", html_record.snapshot_id))
+            }
+        }
+        assert_eq!(
+            ById.relative_directory(&first).unwrap(),
+            format!("by-id/{}", first.snapshot_id)
+        );
+    }
+
+    #[test]
+    fn wayback_timestamps() {
+        use crate::layout::{parse_wayback_timestamp, wayback_timestamp};
+        use chrono::{TimeZone, Utc};
+
+        let date_time = Utc.with_ymd_and_hms(2024, 3, 9, 7, 5, 1).unwrap();
+        assert_eq!(wayback_timestamp(&date_time), "20240309070501");
+        assert_eq!(
+            parse_wayback_timestamp("20240309070501").unwrap(),
+            date_time
+        );
+        assert_eq!(
+            parse_wayback_timestamp("20240309070501_k3j9x0qa").unwrap(),
+            date_time
+        );
+        assert!(parse_wayback_timestamp("09-03-2024-07:05:01").is_err());
+        assert!(parse_wayback_timestamp("20241309070501").is_err());
+
+        //plain string order is time order
+        let later = Utc.with_ymd_and_hms(2024, 10, 1, 0, 0, 0).unwrap();
+        assert!(wayback_timestamp(&date_time) < wayback_timestamp(&later));
+    }
+
+    #[test]
+    fn sanitized_paths() {
+        use crate::sanitize::{
+            numbered_file_name, sanitize_component, sanitize_relative_path, MAX_COMPONENT_LENGTH,
+        };
+
+        assert_eq!(sanitize_component("logo.png"), "logo.png");
+        assert_eq!(sanitize_component(".."), "_");
+        assert_eq!(sanitize_component("%2e%2e"), "_");
+        assert_eq!(sanitize_component(""), "_");
+        assert_eq!(sanitize_component("a:b*c?.css"), "a_b_c_.css");
+        assert_eq!(sanitize_component("a%2Fb"), "a_b");
+        assert_eq!(sanitize_component("name. "), "name");
+        assert_eq!(sanitize_component("CON"), "_CON");
+        assert_eq!(sanitize_component("lpt1.txt"), "_lpt1.txt");
+        assert_eq!(sanitize_component("%E2%9C%93.svg"), "\u{2713}.svg");
+
+        let long = format!("{}.png", "a".repeat(300));
+        let short = sanitize_component(&long);
+        assert!(short.len() <= MAX_COMPONENT_LENGTH);
+        assert!(short.ends_with(".png"));
+        assert_ne!(
+            short,
+            sanitize_component(&format!("{}b.png", "a".repeat(300)))
+        );
+
+        assert_eq!(
+            sanitize_relative_path("example.com/../../etc/./passwd"),
+            "example.com/_/_/etc/passwd"
+        );
+        assert_eq!(sanitize_relative_path("/"), "_");
+
+        assert_eq!(
+            numbered_file_name("images/logo.png", 2),
+            "images/logo-2.png"
+        );
+        assert_eq!(numbered_file_name("js/LICENSE", 3), "js/LICENSE-3");
+        assert_eq!(numbered_file_name("css/.hidden", 2), "css/.hidden-2");
+    }
+
+    #[test]
+    fn object_store_shares_files() {
+        use crate::{objects::ObjectStore, snapshot::sha256_hex};
+        use std::fs;
+
+        let base = std::env::temp_dir().join(format!("lolchive-objects-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let store = ObjectStore::new(base);
+
+        let bytes = b"body { color: red }";
+        let hash = sha256_hex(bytes);
+        assert_eq!(
+            store.path_for(&hash),
+            format!("{}/objects/{}/{}", base, &hash[..2], &hash[2..])
+        );
+        assert!(!store.contains(&hash));
+
+        let first = format!("{}/a/css/site.css", base);
+        let second = format!("{}/b/css/site.css", base);
+        assert_eq!(store.link(bytes, &first).unwrap(), hash);
+        assert_eq!(store.link(bytes, &second).unwrap(), hash);
+        assert!(store.contains(&hash));
+        assert_eq!(fs::read(&first).unwrap(), bytes);
+        assert_eq!(fs::read(&second).unwrap(), bytes);
+
+        //one object however often it is linked
+        let objects: Vec<_> = fs::read_dir(format!("{}/objects/{}", base, &hash[..2]))
+            .unwrap()
+            .collect();
+        assert_eq!(objects.len(), 1);
+
+        //relinking a path to different content replaces it
+        store.link(b"body {}", &first).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), b"body {}");
+        assert_eq!(fs::read(&second).unwrap(), bytes);
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn concurrent_downloads_respect_host_limit() {
+        use crate::client::{fetch_assets, ArchiverConfig, HttpClient};
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        //tiny http server answering every request with its path, slowly
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (server_in_flight, server_most) = (in_flight.clone(), most.clone());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (in_flight, most) = (server_in_flight.clone(), server_most.clone());
+                std::thread::spawn(move || {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    let mut request = [0u8; 1024];
+                    let read = stream.read(&mut request).unwrap();
+                    let request = String::from_utf8_lossy(&request[..read]).to_string();
+                    let path = request.split(' ').nth(1).unwrap_or("/").to_string();
+                    std::thread::sleep(Duration::from_millis(50));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        path.len(),
+                        path
+                    );
+                    stream.write_all(response.as_bytes()).unwrap();
+                });
+            }
+        });
+
+        let client = HttpClient::new(ArchiverConfig {
+            max_concurrent_downloads: 8,
+            max_downloads_per_host: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut urls: Vec<String> = (0..6)
+            .map(|n| format!("http://127.0.0.1:{}/asset{}.css", port, n))
+            .collect();
+        urls.push(urls[0].clone());
+
+        let results = aw!(fetch_assets(&client, &urls));
+        assert_eq!(results.len(), 6);
+        for n in 0..6 {
+            let url = format!("http://127.0.0.1:{}/asset{}.css", port, n);
+            let asset = results[&url].as_ref().unwrap();
+            assert_eq!(&asset.bytes[..], format!("/asset{}.css", n).as_bytes());
+        }
+        assert!(most.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn generic_archiver_and_crawler_with_fixture_fetcher() {
+        use crate::{
+            client::{Asset, HttpClient},
+            crawler::Crawler,
+            fetcher::{FetchFuture, FetchedPage, PageFetcher},
+            html::HtmlRecord,
+            web_archiver::Archiver,
+            Error,
+        };
+        use std::collections::HashMap;
+        use std::fs;
+
+        //pages and assets recorded ahead of time, nothing goes over the network
+        struct FixtureFetcher {
+            client: HttpClient,
+            pages: HashMap<String, String>,
+        }
+        impl PageFetcher for FixtureFetcher {
+            fn fetch<'a>(&'a self, url: &'a str) -> FetchFuture<'a> {
+                Box::pin(async move {
+                    let body = self
+                        .pages
+                        .get(url)
+                        .ok_or_else(|| Error::InvalidUrl(format!("no fixture for {}", url)))?;
+                    let mut page = FetchedPage::new(HtmlRecord::new(url.to_string(), body.clone()));
+                    let logo = "http://fixture.test/logo.png".to_string();
+                    page.resources.insert(
+                        logo.clone(),
+                        Asset {
+                            url: logo,
+                            status: 200,
+                            content_type: Some("image/png".to_string()),
+                            bytes: bytes::Bytes::from_static(b"\x89PNG\r\n\x1a\nfixture"),
+                        },
+                    );
+                    page.pdf = Some(b"%PDF-1.7 fixture".to_vec());
+                    Ok(page)
+                })
+            }
+            fn client(&self) -> &HttpClient {
+                &self.client
+            }
+        }
+
+        let mut pages = HashMap::new();
+        pages.insert(
+            "http://fixture.test/".to_string(),
+            r#"<html><body><img src="/logo.png"><a href="/about">about</a></body></html>"#
+                .to_string(),
+        );
+        pages.insert(
+            "http://fixture.test/about".to_string(),
+            r#"<html><body><img src="logo.png"></body></html>"#.to_string(),
+        );
+        let fixtures = || FixtureFetcher {
+            client: HttpClient::default(),
+            pages: pages.clone(),
+        };
+
+        let base = std::env::temp_dir().join(format!("lolchive-fixture-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+
+        let archiver = Archiver::with_fetcher(fixtures());
+        let result = aw!(archiver.create_archive("http://fixture.test/", base)).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(result.assets_saved, 1);
+        let index = fs::read_to_string(result.index_path().unwrap()).unwrap();
+        assert!(index.contains(r#"src="./images/logo.png""#));
+        assert_eq!(
+            fs::read(result.pdf_path.as_ref().unwrap()).unwrap(),
+            b"%PDF-1.7 fixture"
+        );
+        let directory = result.directory.unwrap();
+        assert_eq!(
+            fs::read(format!("{}/images/logo.png", directory)).unwrap(),
+            b"\x89PNG\r\n\x1a\nfixture"
+        );
+
+        let results =
+            aw!(Crawler::with_fetcher(fixtures()).save_crawl("http://fixture.test/", base, 5))
+                .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(results[1].source_url, "http://fixture.test/about");
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn render_heuristics() {
+        use crate::html::HtmlRecord;
+        use crate::render::{visible_text_length, RenderMode, RenderRules};
+
+        let article = format!(
+            "<html><head><title>t</title><style>p {{}}</style></head><body><div id=\"app\"><p>{}</p></div><script>var x = 1;</script></body></html>",
+            "server rendered text ".repeat(20)
+        );
+        let page = |url: &str, body: &str| HtmlRecord::new(url.to_string(), body.to_string());
+        let rules = RenderRules::default();
+
+        assert_eq!(
+            visible_text_length(&page("https://example.com/", &article)),
+            "serverrenderedtext".len() * 20
+        );
+        assert_eq!(
+            rules.needs_rendering(&page("https://example.com/", &article)),
+            None
+        );
+
+        //a shell: almost no text, and the framework root is empty
+        let shell =
+            r#"<html><body><div id="root"></div><script src="/bundle.js"></script></body></html>"#;
+        assert!(rules
+            .needs_rendering(&page("https://example.com/", shell))
+            .unwrap()
+            .contains("visible text"));
+
+        let long_shell = format!(
+            "<html><body><nav>{}</nav><div id=\"__next\"> </div></body></html>",
+            "menu entry ".repeat(40)
+        );
+        assert!(rules
+            .needs_rendering(&page("https://example.com/", &long_shell))
+            .unwrap()
+            .contains("#__next"));
+
+        let noscript = format!(
+            "<html><body><noscript>You need to Enable JavaScript to run this app.</noscript><p>{}</p></body></html>",
+            "text ".repeat(100)
+        );
+        assert!(rules
+            .needs_rendering(&page("https://example.com/", &noscript))
+            .unwrap()
+            .contains("enable javascript"));
+
+        let rules = RenderRules {
+            hosts: vec![
+                ("spa.example.com".to_string(), RenderMode::Always),
+                ("static.test".to_string(), RenderMode::Never),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.mode_for("https://www.static.test/a"),
+            RenderMode::Never
+        );
+        assert_eq!(rules.mode_for("https://notstatic.test/"), RenderMode::Auto);
+        assert!(rules
+            .needs_rendering(&page("https://spa.example.com/", &article))
+            .is_some());
+        assert_eq!(
+            rules.needs_rendering(&page("https://static.test/", shell)),
+            None
+        );
+    }
+
+    #[test]
+    fn wait_strategy_overrides() {
+        use crate::wait::{WaitConfig, WaitStrategy};
+        use regex::Regex;
+        use std::time::Duration;
+
+        let config = WaitConfig {
+            overrides: vec![
+                (
+                    Regex::new(r"^https://app\.example\.com/").unwrap(),
+                    WaitStrategy::All(vec![
+                        WaitStrategy::Selector("#content .loaded".to_string()),
+                        WaitStrategy::NetworkIdle(Duration::from_millis(500)),
+                    ]),
+                ),
+                (
+                    Regex::new(r"example\.com").unwrap(),
+                    WaitStrategy::Delay(Duration::from_secs(2)),
+                ),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.strategy_for("https://rust-lang.org/"),
+            &WaitStrategy::ReadyState
+        );
+        assert_eq!(
+            config.strategy_for("https://www.example.com/news"),
+            &WaitStrategy::Delay(Duration::from_secs(2))
+        );
+        //the first match wins even though the second pattern matches too
+        assert!(matches!(
+            config.strategy_for("https://app.example.com/dashboard"),
+            WaitStrategy::All(steps) if steps.len() == 2
+        ));
+
+        //a delay longer than the timeout is cut short
+        let config = WaitConfig {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+        let deadline = started + config.timeout;
+        assert!(matches!(
+            aw!(config.delay(Duration::from_secs(10), deadline)),
+            Err(crate::Error::Timeout(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(
+            aw!(config.delay(Duration::from_millis(1), started + Duration::from_secs(5))).is_ok()
+        );
+    }
+
+    #[test]
+    fn stitched_screenshots() {
+        use crate::screenshot::{set_device_pixel_ratio, stitch};
+        use crate::web_archiver::get_capabilities;
+        use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+        use std::io::Cursor;
+
+        let png = |color: [u8; 4]| {
+            //a 3 css pixel tall viewport at device pixel ratio 2
+            let image = RgbaImage::from_pixel(4, 6, Rgba(color));
+            let mut bytes: Vec<u8> = vec![];
+            DynamicImage::ImageRgba8(image)
+                .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+                .unwrap();
+            bytes
+        };
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+
+        //a 5 css pixel page: the second shot could only scroll to 2, not 3
+        let stitched = stitch(&[(0, png(red)), (2, png(blue))], 3, 5).unwrap();
+        let stitched = image::load_from_memory(&stitched).unwrap().to_rgba8();
+        assert_eq!(stitched.dimensions(), (4, 10));
+        assert_eq!(stitched.get_pixel(0, 0).0, red);
+        assert_eq!(stitched.get_pixel(3, 3).0, red);
+        assert_eq!(stitched.get_pixel(0, 4).0, blue);
+        assert_eq!(stitched.get_pixel(3, 9).0, blue);
+
+        assert!(stitch(&[], 3, 5).is_err());
+
+        let mut capabilities = get_capabilities();
+        set_device_pixel_ratio(&mut capabilities, 2.0);
+        assert_eq!(
+            capabilities["moz:firefoxOptions"]["prefs"]["layout.css.devPixelsPerPx"],
+            "2"
+        );
+    }
+
+    #[test]
+    fn print_parameters() {
+        use crate::print::{Margins, Orientation, PrintConfig, LETTER};
+
+        let defaults = PrintConfig::default().parameters();
+        assert_eq!(defaults["orientation"], "portrait");
+        assert_eq!(defaults["page"]["width"], 21.0);
+        assert_eq!(defaults["page"]["height"], 29.7);
+        assert_eq!(defaults["background"], false);
+        assert_eq!(defaults["shrinkToFit"], true);
+
+        let parameters = PrintConfig {
+            page_size: LETTER,
+            margins: Margins {
+                top: 2.0,
+                ..Margins::uniform(0.5)
+            },
+            orientation: Orientation::Landscape,
+            background: true,
+            ..Default::default()
+        }
+        .parameters();
+        assert_eq!(parameters["orientation"], "landscape");
+        assert_eq!(parameters["page"]["width"], 21.59);
+        assert_eq!(parameters["margin"]["top"], 2.0);
+        assert_eq!(parameters["margin"]["left"], 0.5);
+        assert_eq!(parameters["background"], true);
+    }
+
+    #[test]
+    fn warc_exchanges_keep_redirect_hops() {
+        use crate::client::{fetch_raw, HttpClient};
+        use crate::warc::{sha1_digest, WarcWriter};
+        use flate2::read::MultiGzDecoder;
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        //moved -> /new, everything else says hello
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                let response = if request.starts_with("GET /old ") {
+                    "HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let old = format!("http://127.0.0.1:{}/old", port);
+        let new = format!("http://127.0.0.1:{}/new", port);
+        let raw = aw!(fetch_raw(&HttpClient::default(), &old)).unwrap();
+        assert_eq!(raw.url, new);
+        assert_eq!(raw.status, 200);
+        assert_eq!(raw.redirects.len(), 1);
+        assert_eq!(raw.redirects[0].url, old);
+        assert_eq!(raw.redirects[0].status, 301);
+
+        let path = std::env::temp_dir().join("lolchive_warc_exchanges.warc.gz");
+        let mut writer = WarcWriter::create(path.to_str().unwrap()).unwrap();
+        let response_id = writer.write_exchange(&raw).unwrap();
+        drop(writer);
+
+        let mut warc = String::new();
+        MultiGzDecoder::new(std::fs::File::open(&path).unwrap())
+            .read_to_string(&mut warc)
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        //warcinfo, then response and request for /old, then for /new
+        let records: Vec<&str> = warc.split("WARC/1.1\r\n").skip(1).collect();
+        assert_eq!(records.len(), 5);
+        let header = |record: &str, name: &str| -> Option<String> {
+            record
+                .split("\r\n\r\n")
+                .next()
+                .unwrap()
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("{}: ", name)))
+                .map(String::from)
+        };
+        let block = |record: &str| -> String {
+            let (_, block) = record.split_once("\r\n\r\n").unwrap();
+            block.strip_suffix("\r\n\r\n").unwrap().to_string()
+        };
+
+        for (pair, url, status_line) in [
+            (&records[1..3], &old, "HTTP/1.1 301 Moved Permanently\r\n"),
+            (&records[3..5], &new, "HTTP/1.1 200 OK\r\n"),
+        ] {
+            let (response, request) = (pair[0], pair[1]);
+            assert_eq!(header(response, "WARC-Type").unwrap(), "response");
+            assert_eq!(header(request, "WARC-Type").unwrap(), "request");
+            assert_eq!(header(response, "WARC-Target-URI").as_ref(), Some(url));
+            assert_eq!(header(request, "WARC-Target-URI").as_ref(), Some(url));
+            assert_eq!(
+                header(response, "Content-Type").unwrap(),
+                "application/http;msgtype=response"
+            );
+            assert_eq!(
+                header(request, "Content-Type").unwrap(),
+                "application/http;msgtype=request"
+            );
+            assert_eq!(
+                header(request, "WARC-Concurrent-To"),
+                header(response, "WARC-Record-ID")
+            );
+            assert!(block(response).starts_with(status_line));
+            assert!(block(request).starts_with(&format!(
+                "GET /{} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\n",
+                url.rsplit('/').next().unwrap(),
+                port
+            )));
+            for record in [response, request] {
+                assert_eq!(
+                    header(record, "WARC-Block-Digest").unwrap(),
+                    sha1_digest(block(record).as_bytes())
+                );
+            }
+        }
+        assert_eq!(header(records[3], "WARC-Record-ID").unwrap(), response_id);
+        assert!(block(records[3]).ends_with("\r\n\r\nhello"));
+        assert_eq!(
+            header(records[3], "WARC-Payload-Digest").unwrap(),
+            sha1_digest(b"hello")
+        );
+        //sha1 of nothing, base32
+        assert_eq!(
+            header(records[1], "WARC-Payload-Digest").unwrap(),
+            "sha1:3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ"
+        );
+    }
+
+    #[test]
+    fn one_url_spelled_two_ways_is_stored_once() {
+        use crate::{
+            client::{Asset, HttpClient},
+            fetcher::FetchedPage,
+            html::HtmlRecord,
+            web_archiver::save_fetched_page,
+        };
+        use std::fs;
+
+        let mut page = FetchedPage::new(HtmlRecord::new(
+            "http://fixture.test/".to_string(),
+            r#"<html><body><img src="/logo.png"><img src="http://fixture.test/logo.png">
+<script src="/app.js"></script><script src="app.js"></script></body></html>"#
+                .to_string(),
+        ));
+        //each is only there once, a second download would go over the network and fail
+        for (url, content_type, bytes) in [
+            (
+                "http://fixture.test/logo.png",
+                "image/png",
+                &b"\x89PNG\r\n\x1a\nfixture"[..],
+            ),
+            ("http://fixture.test/app.js", "text/javascript", b"var a;"),
+        ] {
+            page.resources.insert(
+                url.to_string(),
+                Asset {
+                    url: url.to_string(),
+                    status: 200,
+                    content_type: Some(content_type.to_string()),
+                    bytes: bytes::Bytes::copy_from_slice(bytes),
+                },
+            );
+        }
+
+        let base = std::env::temp_dir().join(format!("lolchive-spellings-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let snapshot = aw!(save_fetched_page(&HttpClient::default(), page, base)).unwrap();
+
+        assert_eq!(snapshot.saved().count(), 2);
+        assert_eq!(snapshot.failed().count(), 0);
+        let index = fs::read_to_string(&snapshot.index_path).unwrap();
+        assert_eq!(index.matches(r#"src="./images/logo.png""#).count(), 2);
+        assert_eq!(index.matches(r#"src="./js/app.js""#).count(), 2);
+        assert!(
+            !std::path::Path::new(&format!("{}/images/logo-2.png", snapshot.directory)).exists()
+        );
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn colliding_names_are_numbered_in_document_order() {
+        use crate::{
+            client::{Asset, HttpClient},
+            fetcher::FetchedPage,
+            html::HtmlRecord,
+            web_archiver::save_fetched_page,
+        };
+        use std::fs;
+
+        let body = r#"<img src="/a/logo.png"><img src="/b/logo.png"><img src="/a/logo.png"><img src="/c/logo.png">"#;
+        let record = || HtmlRecord::new("http://fixture.test/".to_string(), body.to_string());
+        let links: Vec<String> = record()
+            .get_image_links()
+            .unwrap()
+            .into_iter()
+            .map(|link| link.0)
+            .collect();
+        assert_eq!(links, vec!["/a/logo.png", "/b/logo.png", "/c/logo.png"]);
+
+        let base = std::env::temp_dir().join(format!("lolchive-numbering-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        //hash set order changes between runs, a few runs would catch it
+        for _ in 0..4 {
+            let mut page = FetchedPage::new(record());
+            for directory in ["a", "b", "c"] {
+                let url = format!("http://fixture.test/{}/logo.png", directory);
+                page.resources.insert(
+                    url.clone(),
+                    Asset {
+                        url,
+                        status: 200,
+                        content_type: Some("image/png".to_string()),
+                        bytes: bytes::Bytes::from(
+                            [&b"\x89PNG\r\n\x1a\n"[..], directory.as_bytes()].concat(),
+                        ),
+                    },
+                );
+            }
+            let snapshot = aw!(save_fetched_page(&HttpClient::default(), page, base)).unwrap();
+            let index = fs::read_to_string(&snapshot.index_path).unwrap();
+            for (directory, local) in [("a", "logo.png"), ("b", "logo-2.png"), ("c", "logo-3.png")]
+            {
+                assert!(index.contains(&format!(r#"src="./images/{}""#, local)));
+                assert!(fs::read(format!("{}/images/{}", snapshot.directory, local))
+                    .unwrap()
+                    .ends_with(directory.as_bytes()));
+            }
+        }
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn busy_host_does_not_hold_other_hosts_back() {
+        use crate::client::{fetch_assets, ArchiverConfig, HttpClient};
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        //records the host of every request as it comes in, answers slowly
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let arrivals = Arc::new(Mutex::new(vec![]));
+        let server_arrivals = arrivals.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let arrivals = server_arrivals.clone();
+                std::thread::spawn(move || {
+                    let mut request = [0u8; 1024];
+                    let read = stream.read(&mut request).unwrap();
+                    let request = String::from_utf8_lossy(&request[..read]).to_lowercase();
+                    let host = request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("host: "))
+                        .unwrap_or("")
+                        .to_string();
+                    arrivals.lock().unwrap().push(host);
+                    std::thread::sleep(Duration::from_millis(100));
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                        )
+                        .unwrap();
+                });
+            }
+        });
+
+        let client = HttpClient::new(ArchiverConfig {
+            max_concurrent_downloads: 2,
+            max_downloads_per_host: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        //127.0.0.1 and localhost are different hosts to the limits
+        let mut urls: Vec<String> = (0..4)
+            .map(|n| format!("http://127.0.0.1:{}/{}.png", port, n))
+            .collect();
+        urls.push(format!("http://localhost:{}/other.png", port));
+
+        let results = aw!(fetch_assets(&client, &urls));
+        assert_eq!(results.len(), 5);
+        assert!(results.values().all(|result| result.is_ok()));
+        let arrivals = arrivals.lock().unwrap();
+        assert!(arrivals[..2].contains(&format!("localhost:{}", port)));
+    }
+
+    #[test]
+    fn padded_href_and_src_are_rewritten() {
+        use crate::{
+            client::{Asset, HttpClient},
+            fetcher::FetchedPage,
+            html::HtmlRecord,
+            web_archiver::save_fetched_page,
+        };
+        use std::fs;
+
+        let record = HtmlRecord::new(
+            "http://fixture.test/".to_string(),
+            r#"<link rel="stylesheet" href=" /t.css "><script src="
+ /a.js "></script>"#
+                .to_string(),
+        );
+        assert_eq!(
+            record.get_css_links().unwrap(),
+            vec![(
+                "/t.css".to_string(),
+                "http://fixture.test/t.css".to_string()
+            )]
+        );
+        assert_eq!(
+            record.get_js_links().unwrap(),
+            vec![("/a.js".to_string(), "http://fixture.test/a.js".to_string())]
+        );
+
+        let mut page = FetchedPage::new(record);
+        for (url, content_type, body) in [
+            ("http://fixture.test/t.css", "text/css", "p {}"),
+            ("http://fixture.test/a.js", "text/javascript", "var a;"),
+        ] {
+            page.resources.insert(
+                url.to_string(),
+                Asset {
+                    url: url.to_string(),
+                    status: 200,
+                    content_type: Some(content_type.to_string()),
+                    bytes: bytes::Bytes::from(body),
+                },
+            );
+        }
+        let base = std::env::temp_dir().join(format!("lolchive-padded-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let snapshot = aw!(save_fetched_page(&HttpClient::default(), page, base)).unwrap();
+        let index = fs::read_to_string(&snapshot.index_path).unwrap();
+        assert!(index.contains(r#"href="./css/t.css""#));
+        assert!(index.contains(r#"src="./js/a.js""#));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn padded_style_attribute_is_rewritten() {
+        use crate::{
+            client::{Asset, HttpClient},
+            fetcher::FetchedPage,
+            html::HtmlRecord,
+            web_archiver::save_fetched_page,
+        };
+        use std::fs;
+
+        let record = HtmlRecord::new(
+            "http://fixture.test/".to_string(),
+            r#"<div style=" background: url(/bg.png) ">x</div>"#.to_string(),
+        );
+        assert_eq!(
+            record.get_inline_styles().unwrap(),
+            vec!["background: url(/bg.png)".to_string()]
+        );
+
+        let mut page = FetchedPage::new(record);
+        let url = "http://fixture.test/bg.png".to_string();
+        page.resources.insert(
+            url.clone(),
+            Asset {
+                url,
+                status: 200,
+                content_type: Some("image/png".to_string()),
+                bytes: bytes::Bytes::from_static(b"\x89PNG\r\n\x1a\nbg"),
+            },
+        );
+        let base = std::env::temp_dir().join(format!("lolchive-style-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let snapshot = aw!(save_fetched_page(&HttpClient::default(), page, base)).unwrap();
+        let index = fs::read_to_string(&snapshot.index_path).unwrap();
+        assert!(index.contains(r#"style="background: url(./images/bg.png)""#));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn hash_percent_and_space_in_asset_names() {
+        use crate::{
+            client::{Asset, HttpClient},
+            fetcher::FetchedPage,
+            html::HtmlRecord,
+            sanitize::encode_path,
+            web_archiver::save_fetched_page,
+        };
+        use std::fs;
+
+        assert_eq!(encode_path("images/logo.png"), "images/logo.png");
+        assert_eq!(encode_path("images/a#b.png"), "images/a%23b.png");
+        assert_eq!(encode_path("images/100%.png"), "images/100%25.png");
+        assert_eq!(encode_path("images/bg one.png"), "images/bg%20one.png");
+
+        let mut page = FetchedPage::new(HtmlRecord::new(
+            "http://fixture.test/".to_string(),
+            r#"<link rel="stylesheet" href="/t.css"><img src="/a%23b.png"><img src="/100%25.png">"#
+                .to_string(),
+        ));
+        for (url, content_type, bytes) in [
+            (
+                "http://fixture.test/t.css",
+                "text/css",
+                &b"p { background: url(bg%20one.png) }"[..],
+            ),
+            (
+                "http://fixture.test/a%23b.png",
+                "image/png",
+                b"\x89PNG\r\n\x1a\na",
+            ),
+            (
+                "http://fixture.test/100%25.png",
+                "image/png",
+                b"\x89PNG\r\n\x1a\nb",
+            ),
+            (
+                "http://fixture.test/bg%20one.png",
+                "image/png",
+                b"\x89PNG\r\n\x1a\nc",
+            ),
+        ] {
+            page.resources.insert(
+                url.to_string(),
+                Asset {
+                    url: url.to_string(),
+                    status: 200,
+                    content_type: Some(content_type.to_string()),
+                    bytes: bytes::Bytes::copy_from_slice(bytes),
+                },
+            );
+        }
+        let base = std::env::temp_dir().join(format!("lolchive-escapes-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let snapshot = aw!(save_fetched_page(&HttpClient::default(), page, base)).unwrap();
+        assert_eq!(snapshot.failed().count(), 0);
+
+        let index = fs::read_to_string(&snapshot.index_path).unwrap();
+        assert!(index.contains(r#"src="./images/a%23b.png""#));
+        assert!(index.contains(r#"src="./images/100%25.png""#));
+        let css = fs::read_to_string(format!("{}/css/t.css", snapshot.directory)).unwrap();
+        assert_eq!(css, "p { background: url(../images/bg%20one.png) }");
+        for name in ["a#b.png", "100%.png", "bg one.png"] {
+            assert!(
+                std::path::Path::new(&format!("{}/images/{}", snapshot.directory, name)).exists()
+            );
+        }
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn windows_1251_stylesheets() {
+        use crate::{
+            charset::{decode_css, detect_css_encoding},
+            client::{Asset, HttpClient},
+            fetcher::FetchedPage,
+            html::HtmlRecord,
+            web_archiver::save_fetched_page,
+        };
+        use std::fs;
+
+        //"\u{41f}\u{440}\u{438}\u{432}\u{435}\u{442}" in windows-1251
+        let with_rule =
+            b"@charset \"windows-1251\";\np::before { content: \"\xcf\xf0\xe8\xe2\xe5\xf2\" }";
+        let plain = b"p::after { content: \"\xcf\xf0\xe8\xe2\xe5\xf2\" }";
+        let hello = "\u{41f}\u{440}\u{438}\u{432}\u{435}\u{442}";
+
+        //bom, then content type, then @charset, then the referrer
+        assert_eq!(
+            detect_css_encoding(with_rule, None, None).name(),
+            "windows-1251"
+        );
+        assert_eq!(
+            detect_css_encoding(with_rule, Some("text/css; charset=koi8-r"), None).name(),
+            "KOI8-R"
+        );
+        assert_eq!(
+            detect_css_encoding(b"\xef\xbb\xbfp {}", Some("text/css; charset=koi8-r"), None).name(),
+            "UTF-8"
+        );
+        assert_eq!(
+            detect_css_encoding(plain, None, Some("windows-1251")).name(),
+            "windows-1251"
+        );
+        assert_eq!(detect_css_encoding(plain, None, None).name(), "UTF-8");
+        let (css, encoding) = decode_css(with_rule, None, None);
+        assert_eq!(css, format!("\np::before {{ content: \"{}\" }}", hello));
+        assert_eq!(encoding.name(), "windows-1251");
+
+        let mut record = HtmlRecord::new(
+            "http://fixture.test/".to_string(),
+            r#"<link rel="stylesheet" href="/rule.css"><link rel="stylesheet" href="/plain.css">"#
+                .to_string(),
+        );
+        record.charset = Some("windows-1251".to_string());
+        let mut page = FetchedPage::new(record);
+        for (url, bytes) in [
+            ("http://fixture.test/rule.css", &with_rule[..]),
+            ("http://fixture.test/plain.css", &plain[..]),
+        ] {
+            page.resources.insert(
+                url.to_string(),
+                Asset {
+                    url: url.to_string(),
+                    status: 200,
+                    content_type: Some("text/css".to_string()),
+                    bytes: bytes::Bytes::copy_from_slice(bytes),
+                },
+            );
+        }
+        let base = std::env::temp_dir().join(format!("lolchive-1251-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let snapshot = aw!(save_fetched_page(&HttpClient::default(), page, base)).unwrap();
+        let rule = fs::read_to_string(format!("{}/css/rule.css", snapshot.directory)).unwrap();
+        assert_eq!(rule, format!("\np::before {{ content: \"{}\" }}", hello));
+        let plain = fs::read_to_string(format!("{}/css/plain.css", snapshot.directory)).unwrap();
+        assert_eq!(plain, format!("p::after {{ content: \"{}\" }}", hello));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn failed_assets_are_downloaded_once() {
+        use crate::{client::HttpClient, fetcher::FetchedPage, html::HtmlRecord};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::sync::{Arc, Mutex};
+
+        //counts the requests per path, the image is missing
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests: Arc<Mutex<HashMap<String, usize>>> = Arc::new(Mutex::new(HashMap::new()));
+        let server_requests = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                let path = request.split(' ').nth(1).unwrap_or("").to_string();
+                *server_requests
+                    .lock()
+                    .unwrap()
+                    .entry(path.clone())
+                    .or_insert(0) += 1;
+                let response: &[u8] = if path == "/style.css" {
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nContent-Length: 35\r\nConnection: close\r\n\r\nbody { background: url(/gone.png) }"
+                } else {
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                stream.write_all(response).unwrap();
+            }
+        });
+
+        let page = FetchedPage::new(HtmlRecord::new(
+            format!("http://127.0.0.1:{}/", port),
+            r#"<img src="/gone.png"><link rel="stylesheet" href="/style.css">"#.to_string(),
+        ));
+        let base = std::env::temp_dir().join(format!("lolchive-failed-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let snapshot = aw!(crate::web_archiver::save_fetched_page(
+            &HttpClient::default(),
+            page,
+            base
+        ))
+        .unwrap();
+
+        assert!(
+            fs::read_to_string(format!("{}/css/style.css", snapshot.directory))
+                .unwrap()
+                .contains("gone.png")
+        );
+        assert_eq!(requests.lock().unwrap().get("/gone.png"), Some(&1));
+        let gone = format!("http://127.0.0.1:{}/gone.png", port);
+        let failed: Vec<_> = snapshot
+            .resources
+            .iter()
+            .filter(|entry| entry.url == gone)
+            .collect();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].error.is_some());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn warc_follows_stylesheets_and_lists_failures() {
+        use crate::{client::HttpClient, html::HtmlRecord, web_archiver::save_warc};
+        use flate2::read::MultiGzDecoder;
+        use std::fs;
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        //a stylesheet importing another, which points at a missing image
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                let (status, content_type, body) = match request.split(' ').nth(1) {
+                    Some("/style.css") => ("200 OK", "text/css", "@import \"more.css\";"),
+                    Some("/more.css") => ("200 OK", "text/css", "p { background: url(gone.png) }"),
+                    Some("/logo.png") => ("200 OK", "image/png", "png"),
+                    _ => ("404 Not Found", "text/plain", ""),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let record = HtmlRecord::new(
+            format!("http://127.0.0.1:{}/", port),
+            r#"<img src="/logo.png"><link rel="stylesheet" href="/style.css">"#.to_string(),
+        );
+        let base = std::env::temp_dir().join(format!("lolchive-warc-{}", std::process::id()));
+        let base = base.to_str().unwrap();
+        let _ = fs::remove_dir_all(base);
+        let path = aw!(save_warc(&HttpClient::default(), record, base, None, None)).unwrap();
+        let directory = path.trim_end_matches("/archive.warc.gz");
+
+        let mut warc = String::new();
+        MultiGzDecoder::new(fs::File::open(&path).unwrap())
+            .read_to_string(&mut warc)
+            .unwrap();
+        for file in ["logo.png", "style.css", "more.css", "gone.png"] {
+            assert!(warc.contains(&format!(
+                "WARC-Target-URI: http://127.0.0.1:{}/{}\r\n",
+                port, file
+            )));
+        }
+
+        let manifest: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(format!("{}/manifest.json", directory)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(manifest["index"], "archive.warc.gz");
+        let resources = manifest["resources"].as_array().unwrap();
+        assert_eq!(resources.len(), 4);
+        let gone = resources
+            .iter()
+            .find(|entry| entry["url"] == format!("http://127.0.0.1:{}/gone.png", port))
+            .unwrap();
+        assert_eq!(gone["status"], 404);
+        assert!(gone["error"].is_string());
+        assert!(fs::metadata(format!("{}/metadata.json", directory)).is_ok());
+
+        fs::remove_dir_all(base).unwrap();
+    }
 }
@@ -0,0 +1,315 @@
+use crate::client::*;
+use crate::css::css_references;
+use crate::error::Error;
+use crate::html::{HtmlRecord, IMAGE_ATTRIBUTES, RESOURCE_ATTRIBUTES};
+use crate::mime::guess_mime;
+use crate::rewrite::DomRewriter;
+use crate::web_archiver::{snapshot_directory, write_metadata};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, Rng};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use url::Url;
+
+///one part of a multipart/related document,
+/// content_location is the absolute url the page refers to it by.
+#[derive(Debug, Clone)]
+pub struct MhtmlPart {
+    pub content_location: String,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+///builds an rfc 2557 multipart/related (.mhtml) document,
+/// the first part added is the root (the page itself).
+pub struct MhtmlWriter {
+    pub boundary: String,
+    pub subject: Option<String>,
+    parts: Vec<MhtmlPart>,
+}
+
+impl MhtmlWriter {
+    pub fn new() -> Self {
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+        MhtmlWriter {
+            boundary: format!("----MultipartBoundary--{}----", random),
+            subject: None,
+            parts: vec![],
+        }
+    }
+
+    ///adds a part, a second part for the same location is ignored.
+    pub fn add_part(&mut self, content_location: &str, content_type: &str, body: Vec<u8>) {
+        if self
+            .parts
+            .iter()
+            .any(|part| part.content_location == content_location)
+        {
+            return;
+        }
+        self.parts.push(MhtmlPart {
+            content_location: content_location.to_string(),
+            content_type: content_type.to_string(),
+            body,
+        });
+    }
+
+    pub fn parts(&self) -> &[MhtmlPart] {
+        &self.parts
+    }
+
+    ///the whole document, text parts are quoted-printable, everything else base64.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+        out.push_str("From: <Saved by lolchive>\r\n");
+        if let Some(root) = self.parts.first() {
+            out.push_str(&format!(
+                "Snapshot-Content-Location: {}\r\n",
+                root.content_location
+            ));
+        }
+        if let Some(subject) = &self.subject {
+            out.push_str(&format!("Subject: {}\r\n", encode_header(subject)));
+        }
+        out.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+        out.push_str("MIME-Version: 1.0\r\n");
+        out.push_str(&format!(
+            "Content-Type: multipart/related;\r\n\ttype=\"text/html\";\r\n\tboundary=\"{}\"\r\n\r\n",
+            self.boundary
+        ));
+
+        for part in &self.parts {
+            out.push_str(&format!("--{}\r\n", self.boundary));
+            out.push_str(&format!("Content-Type: {}\r\n", part.content_type));
+            if is_text(&part.content_type) {
+                out.push_str("Content-Transfer-Encoding: quoted-printable\r\n");
+                out.push_str(&format!(
+                    "Content-Location: {}\r\n\r\n",
+                    part.content_location
+                ));
+                out.push_str(&quoted_printable(&part.body));
+            } else {
+                out.push_str("Content-Transfer-Encoding: base64\r\n");
+                out.push_str(&format!(
+                    "Content-Location: {}\r\n\r\n",
+                    part.content_location
+                ));
+                out.push_str(&wrapped_base64(&part.body));
+            }
+            out.push_str("\r\n");
+        }
+        out.push_str(&format!("--{}--\r\n", self.boundary));
+        out.into_bytes()
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        File::create(path)?.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+}
+
+impl Default for MhtmlWriter {
+    fn default() -> Self {
+        MhtmlWriter::new()
+    }
+}
+
+/// public function:
+/// packages the page and every image/css/js/resource save_page would collect
+/// (plus what the stylesheets point at) into {snapshot}/archive.mhtml.
+/// asset references in the page are made absolute so they match the
+/// Content-Location of their part.
+/// returns the path to archive.mhtml
+pub async fn save_mhtml(
+    client: &HttpClient,
+    html_record: HtmlRecord,
+    base_path: &str,
+) -> Result<String, Error> {
+    let directory = snapshot_directory(client, &html_record, base_path)?;
+    fs::create_dir_all(directory.clone())?;
+
+    //original attribute value -> absolute url
+    let mut absolute_map: HashMap<String, String> = HashMap::new();
+    let mut asset_links: Vec<String> = vec![];
+    let mut css_links: Vec<String> = vec![];
+
+    for links in [html_record.get_image_links(), html_record.get_js_links()]
+        .into_iter()
+        .flatten()
+    {
+        for (raw, link) in links {
+            asset_links.push(link.clone());
+            absolute_map.insert(raw, link);
+        }
+    }
+    if let Some(links) = html_record.get_css_links() {
+        for (raw, link) in links {
+            css_links.push(link.clone());
+            absolute_map.insert(raw, link);
+        }
+    }
+    if let Some(links) = html_record.get_resource_links() {
+        for (raw, link, _) in links {
+            asset_links.push(link.clone());
+            absolute_map.insert(raw, link);
+        }
+    }
+    //inline styles resolve against the page
+    let origin = Url::parse(&html_record.origin)?;
+    for style in html_record.get_inline_styles().unwrap_or_default() {
+        for reference in css_references(&style) {
+            if let Ok(url) = origin.join(&reference.url) {
+                if reference.import {
+                    css_links.push(url.to_string());
+                } else {
+                    asset_links.push(url.to_string());
+                }
+            }
+        }
+    }
+
+    let mut rewriter = DomRewriter::new(&html_record);
+    for (tag, attribute, is_srcset) in IMAGE_ATTRIBUTES {
+        if is_srcset {
+            rewriter.rewrite_srcset(tag, attribute, &absolute_map)?;
+        } else {
+            rewriter.rewrite_attribute(tag, attribute, &absolute_map)?;
+        }
+    }
+    rewriter.promote_lazy(&absolute_map)?;
+    rewriter.rewrite_attribute("link[rel~=stylesheet]", "href", &absolute_map)?;
+    rewriter.rewrite_attribute("script", "src", &absolute_map)?;
+    for (tag, attribute, _) in RESOURCE_ATTRIBUTES {
+        rewriter.rewrite_attribute(tag, attribute, &absolute_map)?;
+    }
+    rewriter.declare_utf8()?;
+
+    let mut writer = MhtmlWriter::new();
+    writer.subject = html_record
+        .tag_text("title")
+        .ok()
+        .flatten()
+        .and_then(|titles| titles.into_iter().next());
+    writer.add_part(
+        &html_record.origin,
+        "text/html; charset=utf-8",
+        rewriter.html().into_bytes(),
+    );
+
+    let mut seen: HashSet<String> = HashSet::new();
+    seen.insert(html_record.origin.clone());
+
+    //stylesheets first, what they point at joins the queue
+    while let Some(link) = css_links.pop() {
+        if !seen.insert(link.clone()) {
+            continue;
+        }
+        let asset = match fetch_asset(client, &link).await {
+            Ok(asset) => asset,
+            Err(_) => continue,
+        };
+        let base = Url::parse(&link)?;
+        for reference in css_references(&String::from_utf8_lossy(&asset.bytes)) {
+            if let Ok(url) = base.join(&reference.url) {
+                if reference.import {
+                    css_links.push(url.to_string());
+                } else {
+                    asset_links.push(url.to_string());
+                }
+            }
+        }
+        let content_type = asset
+            .content_type
+            .clone()
+            .unwrap_or_else(|| "text/css".to_string());
+        writer.add_part(&link, &content_type, asset.bytes.to_vec());
+    }
+
+    for link in asset_links {
+        if !seen.insert(link.clone()) {
+            continue;
+        }
+        if let Ok(asset) = fetch_asset(client, &link).await {
+            let content_type = guess_mime(&link, asset.content_type.as_deref(), &asset.bytes);
+            writer.add_part(&link, &content_type, asset.bytes.to_vec());
+        }
+    }
+
+    let path = format!("{}/archive.mhtml", directory);
+    writer.write(&path)?;
+    write_metadata(&directory, &html_record)?;
+    Ok(path)
+}
+
+fn is_text(content_type: &str) -> bool {
+    let lower = content_type.to_lowercase();
+    lower.starts_with("text/")
+        || lower.contains("javascript")
+        || lower.contains("json")
+        || lower.contains("xml")
+}
+
+///rfc 2045 quoted-printable, line breaks become crlf,
+/// lines are soft broken before they pass 76 characters.
+pub fn quoted_printable(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + bytes.len() / 8);
+    let mut line_length = 0;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let byte = bytes[index];
+        if byte == b'\r' && bytes.get(index + 1) == Some(&b'\n') {
+            index += 1;
+            continue;
+        }
+        if byte == b'\n' {
+            out.push_str("\r\n");
+            line_length = 0;
+            index += 1;
+            continue;
+        }
+        let at_line_end = matches!(bytes.get(index + 1), None | Some(b'\r') | Some(b'\n'));
+        let encoded = match byte {
+            b' ' | b'\t' if !at_line_end => (byte as char).to_string(),
+            33..=60 | 62..=126 => (byte as char).to_string(),
+            _ => format!("={:02X}", byte),
+        };
+        if line_length + encoded.len() > 75 {
+            out.push_str("=\r\n");
+            line_length = 0;
+        }
+        out.push_str(&encoded);
+        line_length += encoded.len();
+        index += 1;
+    }
+    out
+}
+
+fn wrapped_base64(bytes: &[u8]) -> String {
+    let encoded = STANDARD.encode(bytes);
+    let mut out = String::with_capacity(encoded.len() + encoded.len() / 38);
+    for (index, chunk) in encoded.as_bytes().chunks(76).enumerate() {
+        if index > 0 {
+            out.push_str("\r\n");
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+    }
+    out
+}
+
+///non ascii header values go out as an rfc 2047 encoded word.
+fn encode_header(value: &str) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+    if value.is_ascii() {
+        value
+    } else {
+        format!("=?utf-8?B?{}?=", STANDARD.encode(value.as_bytes()))
+    }
+}
@@ -0,0 +1,129 @@
+///public function:
+/// guesses an image type from its first bytes, returns the mime type.
+/// covers what browsers commonly render: png, jpeg, gif, webp, avif, ico, bmp, tiff and svg.
+pub fn sniff_image(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if bytes.len() >= 12
+        && &bytes[4..8] == b"ftyp"
+        && (&bytes[8..12] == b"avif" || &bytes[8..12] == b"avis")
+    {
+        Some("image/avif")
+    } else if bytes.starts_with(b"\x00\x00\x01\x00") {
+        Some("image/x-icon")
+    } else if bytes.starts_with(b"BM") {
+        Some("image/bmp")
+    } else if bytes.starts_with(b"II*\x00") || bytes.starts_with(b"MM\x00*") {
+        Some("image/tiff")
+    } else if looks_like_svg(bytes) {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+///public function:
+/// "text/html; charset=utf-8" -> "text/html"
+pub fn content_type_essence(content_type: &str) -> Option<String> {
+    let essence = content_type.split(';').next()?.trim().to_lowercase();
+    if essence.is_empty() {
+        None
+    } else {
+        Some(essence)
+    }
+}
+
+///public function:
+/// file extension (without the dot) for a mime type.
+pub fn extension_for_mime(mime: &str) -> Option<&'static str> {
+    match content_type_essence(mime)?.as_str() {
+        "image/png" => Some("png"),
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/avif" => Some("avif"),
+        "image/x-icon" | "image/vnd.microsoft.icon" => Some("ico"),
+        "image/bmp" => Some("bmp"),
+        "image/tiff" => Some("tiff"),
+        "image/svg+xml" => Some("svg"),
+        "text/css" => Some("css"),
+        "text/javascript" | "application/javascript" | "application/x-javascript" => Some("js"),
+        "text/html" => Some("html"),
+        _ => None,
+    }
+}
+
+///public function:
+/// extension for a downloaded image, the bytes win over the header
+/// since servers mislabel images all the time.
+pub fn image_extension(content_type: Option<&str>, bytes: &[u8]) -> Option<&'static str> {
+    sniff_image(bytes)
+        .and_then(extension_for_mime)
+        .or_else(|| content_type.and_then(extension_for_mime))
+}
+
+///public function:
+/// best guess at the mime type of a downloaded resource: the content type header
+/// (unless it is the generic octet-stream), then the magic bytes, then the extension.
+pub fn guess_mime(url: &str, content_type: Option<&str>, bytes: &[u8]) -> String {
+    if let Some(essence) = content_type.and_then(content_type_essence) {
+        if essence != "application/octet-stream" {
+            return essence;
+        }
+    }
+    if let Some(mime) = sniff_image(bytes) {
+        return mime.to_string();
+    }
+    mime_for_extension(url)
+        .unwrap_or("application/octet-stream")
+        .to_string()
+}
+
+///public function:
+/// mime type from the extension of the url path.
+pub fn mime_for_extension(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let last = path.rsplit('/').next()?;
+    let (_, extension) = last.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "avif" => Some("image/avif"),
+        "ico" => Some("image/x-icon"),
+        "bmp" => Some("image/bmp"),
+        "svg" => Some("image/svg+xml"),
+        "woff" => Some("font/woff"),
+        "woff2" => Some("font/woff2"),
+        "ttf" => Some("font/ttf"),
+        "otf" => Some("font/otf"),
+        "eot" => Some("application/vnd.ms-fontobject"),
+        "css" => Some("text/css"),
+        "js" | "mjs" => Some("text/javascript"),
+        "json" => Some("application/json"),
+        "webmanifest" => Some("application/manifest+json"),
+        "html" | "htm" => Some("text/html"),
+        "mp4" => Some("video/mp4"),
+        "webm" => Some("video/webm"),
+        "mp3" => Some("audio/mpeg"),
+        "ogg" => Some("audio/ogg"),
+        "wav" => Some("audio/wav"),
+        "vtt" => Some("text/vtt"),
+        "pdf" => Some("application/pdf"),
+        _ => None,
+    }
+}
+
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<svg") || head.starts_with("<!--"))
+        && head.contains("<svg")
+}
@@ -0,0 +1,84 @@
+use crate::error::Error;
+use crate::snapshot::sha256_hex;
+use rand::{distributions::Alphanumeric, Rng};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+///content addressed store shared by every snapshot under one base path.
+/// a file lives once at objects/{first 2 hex digits}/{remaining 62} of its sha-256,
+/// snapshot directories get hard links to it (or a copy where links are not possible),
+/// so the same stylesheet or logo captured on every page only takes disk space once.
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    pub root: String,
+}
+
+impl ObjectStore {
+    ///public function:
+    /// the store of the snapshots under base_path, base_path/objects
+    pub fn new(base_path: &str) -> Self {
+        ObjectStore {
+            root: format!("{}/objects", base_path.trim_end_matches('/')),
+        }
+    }
+
+    ///public method:
+    /// where the object with this hex sha-256 is stored
+    pub fn path_for(&self, sha256: &str) -> String {
+        format!("{}/{}/{}", self.root, &sha256[..2], &sha256[2..])
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        Path::new(&self.path_for(sha256)).is_file()
+    }
+
+    ///public method:
+    /// stores the bytes unless an identical object is already there,
+    /// returns the hex sha-256 they are keyed by.
+    /// written to a temporary file first so a half written object is never visible.
+    pub fn put(&self, bytes: &[u8]) -> Result<String, Error> {
+        let sha256 = sha256_hex(bytes);
+        let path = self.path_for(&sha256);
+        if Path::new(&path).is_file() {
+            return Ok(sha256);
+        }
+        let parent = format!("{}/{}", self.root, &sha256[..2]);
+        fs::create_dir_all(&parent)?;
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+        let temporary = format!("{}/.tmp-{}", parent, suffix);
+        File::create(&temporary)?.write_all(bytes)?;
+        if let Err(e) = fs::rename(&temporary, &path) {
+            let _ = fs::remove_file(&temporary);
+            //another capture stored the same object in the meantime
+            if !Path::new(&path).is_file() {
+                return Err(e.into());
+            }
+        }
+        Ok(sha256)
+    }
+
+    ///public method:
+    /// stores the bytes and makes destination a hard link to the object,
+    /// falls back to copying it (different filesystem, no hard link support).
+    /// returns the hex sha-256.
+    pub fn link(&self, bytes: &[u8], destination: &str) -> Result<String, Error> {
+        let sha256 = self.put(bytes)?;
+        if let Some((parent, _)) = destination.rsplit_once('/') {
+            fs::create_dir_all(parent)?;
+        }
+        if Path::new(destination).exists() {
+            fs::remove_file(destination)?;
+        }
+        let object = self.path_for(&sha256);
+        if fs::hard_link(&object, destination).is_err() {
+            fs::copy(&object, destination)?;
+        }
+        Ok(sha256)
+    }
+}
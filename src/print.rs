@@ -0,0 +1,126 @@
+use crate::error::Error;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use fantoccini::wd::WebDriverCompatibleCommand;
+use fantoccini::Client;
+use serde_json::{json, Value};
+
+///A4 in centimetres, the unit webdriver print uses.
+pub const A4: (f64, f64) = (21.0, 29.7);
+///US letter in centimetres.
+pub const LETTER: (f64, f64) = (21.59, 27.94);
+///US legal in centimetres.
+pub const LEGAL: (f64, f64) = (21.59, 35.56);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+///page margins in centimetres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Margins {
+    pub top: f64,
+    pub bottom: f64,
+    pub left: f64,
+    pub right: f64,
+}
+
+impl Margins {
+    ///the same margin on every side.
+    pub fn uniform(margin: f64) -> Self {
+        Margins {
+            top: margin,
+            bottom: margin,
+            left: margin,
+            right: margin,
+        }
+    }
+}
+
+///how page.pdf is printed, set fetcher::BrowserConfig::print to get one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintConfig {
+    ///(width, height) in centimetres, see A4, LETTER and LEGAL.
+    pub page_size: (f64, f64),
+    pub margins: Margins,
+    pub orientation: Orientation,
+    ///print background colours and images, browsers leave them out by default.
+    pub background: bool,
+    ///0.1 to 2.0
+    pub scale: f64,
+    ///shrink wide pages to the page width instead of cutting them off.
+    pub shrink_to_fit: bool,
+}
+
+impl Default for PrintConfig {
+    fn default() -> Self {
+        PrintConfig {
+            page_size: A4,
+            margins: Margins::uniform(1.0),
+            orientation: Orientation::Portrait,
+            background: false,
+            scale: 1.0,
+            shrink_to_fit: true,
+        }
+    }
+}
+
+impl PrintConfig {
+    ///public method:
+    /// the body of the webdriver print command.
+    pub fn parameters(&self) -> Value {
+        json!({
+            "orientation": match self.orientation {
+                Orientation::Portrait => "portrait",
+                Orientation::Landscape => "landscape",
+            },
+            "scale": self.scale,
+            "background": self.background,
+            "page": {
+                "width": self.page_size.0,
+                "height": self.page_size.1,
+            },
+            "margin": {
+                "top": self.margins.top,
+                "bottom": self.margins.bottom,
+                "left": self.margins.left,
+                "right": self.margins.right,
+            },
+            "shrinkToFit": self.shrink_to_fit,
+        })
+    }
+}
+
+///POST /session/{id}/print, webdriver's "Print Page".
+#[derive(Debug)]
+struct PrintPage(Value);
+
+impl WebDriverCompatibleCommand for PrintPage {
+    fn endpoint(
+        &self,
+        base_url: &url::Url,
+        session_id: Option<&str>,
+    ) -> Result<url::Url, url::ParseError> {
+        base_url.join(&format!("session/{}/print", session_id.unwrap_or_default()))
+    }
+
+    fn method_and_body(&self, _request_url: &url::Url) -> (http::Method, Option<String>) {
+        (http::Method::POST, Some(self.0.to_string()))
+    }
+}
+
+///public function:
+/// prints the page the browser is on, returns the pdf.
+pub async fn print_page(fclient: &Client, config: &PrintConfig) -> Result<Vec<u8>, Error> {
+    match fclient.issue_cmd(PrintPage(config.parameters())).await? {
+        Value::String(encoded) => STANDARD
+            .decode(encoded)
+            .map_err(|e| Error::Parse(format!("printed pdf: {}", e))),
+        other => Err(Error::WebDriver(format!(
+            "print returned {} instead of a pdf",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,168 @@
+use crate::html::HtmlRecord;
+use scraper::{ElementRef, Selector};
+use url::Url;
+
+///elements whose text never shows up on the page.
+const HIDDEN_TAGS: [&str; 5] = ["script", "style", "noscript", "template", "head"];
+
+///what to do with the pages of a host in hybrid mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    ///decide from the page itself, see needs_rendering.
+    Auto,
+    ///always render in the browser.
+    Always,
+    ///never render, keep what reqwest got.
+    Never,
+}
+
+///when HybridFetcher re-captures a page in the browser.
+#[derive(Debug, Clone)]
+pub struct RenderRules {
+    ///pages with fewer visible characters (whitespace not counted) are rendered.
+    pub min_text_length: usize,
+    ///selectors of the elements javascript frameworks mount into,
+    /// the page is rendered when one is there but has no visible text.
+    pub spa_roots: Vec<String>,
+    ///the page is rendered when a <noscript> contains one of these (case insensitive).
+    pub noscript_hints: Vec<String>,
+    ///per host overrides, "example.com" also covers its subdomains.
+    /// the first matching rule wins, hosts without one are Auto.
+    pub hosts: Vec<(String, RenderMode)>,
+}
+
+impl Default for RenderRules {
+    fn default() -> Self {
+        RenderRules {
+            min_text_length: 200,
+            spa_roots: [
+                "#root",
+                "#app",
+                "#__next",
+                "#__nuxt",
+                "#___gatsby",
+                "#svelte",
+                "[ng-version]",
+                "[ng-app]",
+                "[data-reactroot]",
+                "app-root",
+            ]
+            .iter()
+            .map(|selector| selector.to_string())
+            .collect(),
+            noscript_hints: [
+                "enable javascript",
+                "javascript is required",
+                "requires javascript",
+                "javascript to run this app",
+                "turn on javascript",
+            ]
+            .iter()
+            .map(|hint| hint.to_string())
+            .collect(),
+            hosts: vec![],
+        }
+    }
+}
+
+impl RenderRules {
+    ///public method:
+    /// the per host rule for url, Auto when none matches.
+    pub fn mode_for(&self, url: &str) -> RenderMode {
+        let host = match Url::parse(url) {
+            Ok(url) => url.host_str().unwrap_or("").to_lowercase(),
+            Err(_) => return RenderMode::Auto,
+        };
+        self.hosts
+            .iter()
+            .find(|(rule, _)| {
+                let rule = rule.to_lowercase();
+                host == rule || host.ends_with(&format!(".{}", rule))
+            })
+            .map(|(_, mode)| *mode)
+            .unwrap_or(RenderMode::Auto)
+    }
+
+    ///public method:
+    /// why the page fetched without javascript needs to be rendered,
+    /// none when it looks complete as it is.
+    pub fn needs_rendering(&self, html_record: &HtmlRecord) -> Option<String> {
+        match self.mode_for(&html_record.origin) {
+            RenderMode::Always => return Some("host is always rendered".to_string()),
+            RenderMode::Never => return None,
+            RenderMode::Auto => {}
+        }
+
+        let text_length = visible_text_length(html_record);
+        if text_length < self.min_text_length {
+            return Some(format!(
+                "only {} characters of visible text (less than {})",
+                text_length, self.min_text_length
+            ));
+        }
+
+        let noscript = Selector::parse("noscript").unwrap();
+        for element in html_record.html.select(&noscript) {
+            let text = element.text().collect::<String>().to_lowercase();
+            if let Some(hint) = self
+                .noscript_hints
+                .iter()
+                .find(|hint| text.contains(&hint.to_lowercase()))
+            {
+                return Some(format!("<noscript> says \"{}\"", hint));
+            }
+        }
+
+        for root in &self.spa_roots {
+            //a bad selector in the rules is skipped, not fatal
+            let selector = match Selector::parse(root) {
+                Ok(selector) => selector,
+                Err(_) => continue,
+            };
+            if let Some(element) = html_record.html.select(&selector).next() {
+                if visible_text(element).trim().is_empty() {
+                    return Some(format!("empty application root {}", root));
+                }
+            }
+        }
+        None
+    }
+}
+
+///public function:
+/// characters (whitespace not counted) of text a reader would see in the body.
+pub fn visible_text_length(html_record: &HtmlRecord) -> usize {
+    let body = Selector::parse("body").unwrap();
+    html_record
+        .html
+        .select(&body)
+        .map(|element| {
+            visible_text(element)
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .count()
+        })
+        .sum()
+}
+
+///text under the element, leaving out scripts, styles and the like.
+fn visible_text(element: ElementRef) -> String {
+    element
+        .descendants()
+        .filter_map(|node| {
+            let text = node.value().as_text()?;
+            let hidden = node.ancestors().any(|ancestor| {
+                ancestor
+                    .value()
+                    .as_element()
+                    .is_some_and(|element| HIDDEN_TAGS.contains(&element.name()))
+            });
+            if hidden {
+                None
+            } else {
+                Some(text.to_string())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
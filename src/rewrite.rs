@@ -0,0 +1,298 @@
+use crate::error::Error;
+use crate::html::{srcset_candidates, HtmlRecord};
+use html5ever::{namespace_url, ns, Attribute, LocalName, QualName};
+use scraper::node::{Element, Text};
+use scraper::{Html, Node};
+use std::collections::HashMap;
+
+///rewrites asset references on the parsed document itself,
+/// only the attributes (and style text) asked for are touched,
+/// everything else is serialized back exactly as it was parsed.
+pub struct DomRewriter {
+    html: Html,
+}
+
+impl DomRewriter {
+    pub fn new(html_record: &HtmlRecord) -> Self {
+        DomRewriter {
+            html: html_record.html.clone(),
+        }
+    }
+
+    ///public method:
+    /// every `attribute` on elements matching `selector` whose whole value is a key
+    /// in replacements gets the mapped value. "/a.png" never touches "/a.png.bak".
+    pub fn rewrite_attribute(
+        &mut self,
+        selector: &str,
+        attribute: &str,
+        replacements: &HashMap<String, String>,
+    ) -> Result<(), Error> {
+        self.rewrite_with(selector, attribute, |value| {
+            replacements.get(value.trim()).cloned()
+        })
+    }
+
+    ///public method:
+    /// same as rewrite_attribute but for srcset style values,
+    /// each candidate url is looked up on its own and the descriptors are kept.
+    pub fn rewrite_srcset(
+        &mut self,
+        selector: &str,
+        attribute: &str,
+        replacements: &HashMap<String, String>,
+    ) -> Result<(), Error> {
+        self.rewrite_with(selector, attribute, |value| {
+            rewrite_srcset_value(value, replacements)
+        })
+    }
+
+    ///public method:
+    /// lazy loaders keep a placeholder in src until a script swaps data-src in,
+    /// offline that script may never run so the local copy of data-src/data-srcset
+    /// goes straight into src/srcset.
+    pub fn promote_lazy(&mut self, replacements: &HashMap<String, String>) -> Result<(), Error> {
+        let selector = HtmlRecord::selector("img, picture source")?;
+        let ids: Vec<_> = self
+            .html
+            .select(&selector)
+            .map(|element| element.id())
+            .collect();
+        for id in ids {
+            if let Some(mut node) = self.html.tree.get_mut(id) {
+                if let Node::Element(element) = node.value() {
+                    for (lazy, eager, is_srcset) in LAZY_ATTRIBUTES {
+                        let promoted = match element.attr(lazy) {
+                            Some(value) if is_srcset => rewrite_srcset_value(value, replacements),
+                            Some(value) => replacements.get(value.trim()).cloned(),
+                            None => None,
+                        };
+                        if let Some(promoted) = promoted {
+                            element.attrs.insert(
+                                QualName::new(None, ns!(), LocalName::from(eager)),
+                                promoted.as_str().into(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///public method:
+    /// swaps the text of <style> blocks and the value of style="" attributes
+    /// for the mapped value when it is a key in replacements.
+    pub fn rewrite_styles(&mut self, replacements: &HashMap<String, String>) -> Result<(), Error> {
+        let style_selector = HtmlRecord::selector("style")?;
+        let text_ids: Vec<_> = self
+            .html
+            .select(&style_selector)
+            .flat_map(|element| element.children().map(|child| child.id()))
+            .collect();
+        for id in text_ids {
+            if let Some(mut node) = self.html.tree.get_mut(id) {
+                if let Node::Text(text) = node.value() {
+                    if let Some(replacement) = replacements.get(&text.text.to_string()) {
+                        text.text = replacement.as_str().into();
+                    }
+                }
+            }
+        }
+        self.rewrite_attribute("[style]", "style", replacements)
+    }
+
+    ///public method:
+    /// turns every <link rel=stylesheet> whose href is a key in contents
+    /// into a <style> block holding the mapped css (media is kept).
+    pub fn inline_stylesheets(&mut self, contents: &HashMap<String, String>) -> Result<(), Error> {
+        let selector = HtmlRecord::selector("link[rel~=stylesheet][href]")?;
+        let ids: Vec<_> = self
+            .html
+            .select(&selector)
+            .map(|element| element.id())
+            .collect();
+        for id in ids {
+            if let Some(mut node) = self.html.tree.get_mut(id) {
+                let css = match node.value() {
+                    Node::Element(element) => match element.attr("href") {
+                        Some(href) => match contents.get(href.trim()) {
+                            Some(css) => css.clone(),
+                            None => continue,
+                        },
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                if let Node::Element(element) = node.value() {
+                    element.name.local = LocalName::from("style");
+                    element.attrs.retain(|name, _| &*name.local == "media");
+                }
+                node.append(Node::Text(Text {
+                    text: escape_raw_text(&css, "style").as_str().into(),
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    ///public method:
+    /// every <script src> whose src is a key in contents loses the src
+    /// and gets the mapped javascript as its body instead.
+    pub fn inline_scripts(&mut self, contents: &HashMap<String, String>) -> Result<(), Error> {
+        let selector = HtmlRecord::selector("script[src]")?;
+        let ids: Vec<_> = self
+            .html
+            .select(&selector)
+            .map(|element| element.id())
+            .collect();
+        for id in ids {
+            if let Some(mut node) = self.html.tree.get_mut(id) {
+                let js = match node.value() {
+                    Node::Element(element) => match element.attr("src") {
+                        Some(src) => match contents.get(src.trim()) {
+                            Some(js) => js.clone(),
+                            None => continue,
+                        },
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                if let Node::Element(element) = node.value() {
+                    element.attrs.retain(|name, _| {
+                        !matches!(&*name.local, "src" | "integrity" | "async" | "defer")
+                    });
+                }
+                node.append(Node::Text(Text {
+                    text: escape_raw_text(&js, "script").as_str().into(),
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    ///public method:
+    /// the archive is always written as utf-8, so every charset declaration
+    /// (<meta charset> and <meta http-equiv=content-type>) is dropped and a
+    /// single <meta charset="utf-8"> goes first in <head>.
+    pub fn declare_utf8(&mut self) -> Result<(), Error> {
+        let selector = HtmlRecord::selector("meta[charset], meta[http-equiv]")?;
+        let ids: Vec<_> = self
+            .html
+            .select(&selector)
+            .filter(|element| {
+                element.value().attr("charset").is_some()
+                    || element
+                        .value()
+                        .attr("http-equiv")
+                        .is_some_and(|value| value.trim().eq_ignore_ascii_case("content-type"))
+            })
+            .map(|element| element.id())
+            .collect();
+        for id in ids {
+            if let Some(mut node) = self.html.tree.get_mut(id) {
+                node.detach();
+            }
+        }
+
+        let head_selector = HtmlRecord::selector("head")?;
+        let head = self
+            .html
+            .select(&head_selector)
+            .next()
+            .map(|head| head.id());
+        if let Some(mut head) = head.and_then(|id| self.html.tree.get_mut(id)) {
+            head.prepend(Node::Element(Element::new(
+                QualName::new(None, ns!(html), LocalName::from("meta")),
+                vec![Attribute {
+                    name: QualName::new(None, ns!(), LocalName::from("charset")),
+                    value: "utf-8".into(),
+                }],
+            )));
+        }
+        Ok(())
+    }
+
+    ///serializes the rewritten document.
+    pub fn html(&self) -> String {
+        self.html.html()
+    }
+
+    fn rewrite_with<F>(
+        &mut self,
+        selector: &str,
+        attribute: &str,
+        mut rewrite: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let selector = HtmlRecord::selector(selector)?;
+        let ids: Vec<_> = self
+            .html
+            .select(&selector)
+            .map(|element| element.id())
+            .collect();
+        for id in ids {
+            if let Some(mut node) = self.html.tree.get_mut(id) {
+                if let Node::Element(element) = node.value() {
+                    for (name, value) in element.attrs.iter_mut() {
+                        if &*name.local == attribute {
+                            if let Some(replacement) = rewrite(value) {
+                                *value = replacement.as_str().into();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+///(lazy attribute, attribute the browser actually loads, is a srcset)
+static LAZY_ATTRIBUTES: [(&str, &str, bool); 3] = [
+    ("data-src", "src", false),
+    ("data-lazy-src", "src", false),
+    ("data-srcset", "srcset", true),
+];
+
+///srcset with every candidate found in replacements swapped,
+/// none when nothing in it needs changing.
+fn rewrite_srcset_value(value: &str, replacements: &HashMap<String, String>) -> Option<String> {
+    let candidates = srcset_candidates(value);
+    if !candidates
+        .iter()
+        .any(|(url, _)| replacements.contains_key(url))
+    {
+        return None;
+    }
+    let rewritten: Vec<String> = candidates
+        .into_iter()
+        .map(|(url, descriptor)| {
+            let url = replacements.get(&url).cloned().unwrap_or(url);
+            if descriptor.is_empty() {
+                url
+            } else {
+                format!("{} {}", url, descriptor)
+            }
+        })
+        .collect();
+    Some(rewritten.join(", "))
+}
+
+///raw text elements end at the first "</tag" (any case), so that sequence
+/// inside inlined css/js has to be broken up.
+fn escape_raw_text(text: &str, tag: &str) -> String {
+    let needle = format!("</{}", tag);
+    let lower = text.to_ascii_lowercase();
+    let mut escaped = String::with_capacity(text.len());
+    let mut last = 0;
+    for (index, _) in lower.match_indices(&needle) {
+        escaped.push_str(&text[last..index]);
+        escaped.push_str("<\\/");
+        last = index + 2;
+    }
+    escaped.push_str(&text[last..]);
+    escaped
+}
@@ -0,0 +1,132 @@
+use crate::snapshot::sha256_hex;
+
+///longest file or directory name written, in bytes.
+/// leaves room under the usual 255 byte limit for numbering and thumbnails.
+pub const MAX_COMPONENT_LENGTH: usize = 120;
+
+///public function:
+/// turns one url path segment (or host) into a name that is safe on
+/// linux, macos and windows: percent-decoded, control characters and
+/// <>:"/\|?* replaced by '_', no trailing dots or spaces, no reserved
+/// windows device names, never empty, "." or "..", and at most
+/// MAX_COMPONENT_LENGTH bytes (long names keep their extension and get a hash).
+pub fn sanitize_component(component: &str) -> String {
+    let decoded = percent_decode(component);
+    let mut name: String = decoded
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let trimmed_length = name.trim_end_matches(['.', ' ']).len();
+    name.truncate(trimmed_length);
+    if name.is_empty() || name.chars().all(|c| c == '.') {
+        name = "_".to_string();
+    }
+    if is_reserved_name(&name) {
+        name.insert(0, '_');
+    }
+    if name.len() > MAX_COMPONENT_LENGTH {
+        name = shorten(&name, component);
+    }
+    name
+}
+
+///public function:
+/// sanitizes every component of a '/' separated relative path,
+/// empty components are dropped and ".." can never climb out of the base.
+pub fn sanitize_relative_path(path: &str) -> String {
+    let components: Vec<String> = path
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(sanitize_component)
+        .collect();
+    if components.is_empty() {
+        "_".to_string()
+    } else {
+        components.join("/")
+    }
+}
+
+///public function:
+/// a relative path as it goes into an attribute or css url():
+/// everything but letters, digits, "-._~" and '/' is percent-encoded,
+/// so "a#b.png" or "bg one.png" point at the file and not a fragment or nowhere.
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+///public function:
+/// "images/logo.png", 2 -> "images/logo-2.png"
+pub fn numbered_file_name(local: &str, number: usize) -> String {
+    let (directory, file_name) = match local.rsplit_once('/') {
+        Some((directory, file_name)) => (format!("{}/", directory), file_name),
+        None => (String::new(), local),
+    };
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}{}-{}.{}", directory, stem, number, extension)
+        }
+        _ => format!("{}{}-{}", directory, file_name, number),
+    }
+}
+
+///cut a long name down, the hash of the original keeps cut names apart.
+fn shorten(name: &str, original: &str) -> String {
+    let hash = &sha256_hex(original.as_bytes())[..8];
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) if !extension.is_empty() && extension.len() <= 16 => {
+            format!(".{}", extension)
+        }
+        _ => String::new(),
+    };
+    let mut end = MAX_COMPONENT_LENGTH - extension.len() - hash.len() - 1;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}-{}{}", &name[..end], hash, extension)
+}
+
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+    matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit())
+}
+
+///%XX sequences to bytes, invalid utf-8 is replaced.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%'
+            && index + 2 < bytes.len()
+            && bytes[index + 1].is_ascii_hexdigit()
+            && bytes[index + 2].is_ascii_hexdigit()
+        {
+            let hex = [bytes[index + 1], bytes[index + 2]];
+            if let Ok(byte) = u8::from_str_radix(std::str::from_utf8(&hex).unwrap_or(""), 16) {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
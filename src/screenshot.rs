@@ -0,0 +1,194 @@
+use crate::error::Error;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use fantoccini::wd::WebDriverCompatibleCommand;
+use fantoccini::Client;
+use image::{imageops, DynamicImage, ImageOutputFormat, RgbaImage};
+use serde_json::{Map, Value};
+use std::io::Cursor;
+use std::time::Duration;
+
+///how much of the page screenshot.png shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotMode {
+    ///what is in the window, the top of the page.
+    Viewport,
+    ///the whole scrollable page.
+    FullPage,
+}
+
+///screenshot settings of the browser fetchers, part of fetcher::BrowserConfig.
+#[derive(Debug, Clone)]
+pub struct ScreenshotConfig {
+    pub mode: ScreenshotMode,
+    ///css pixel size of the viewport (not the window) set when the session starts,
+    /// none keeps whatever the browser opened with.
+    pub viewport: Option<(u32, u32)>,
+    ///device pixels per css pixel, 2.0 gives retina sized screenshots.
+    /// set as a firefox pref when the session starts, none keeps the default.
+    pub device_pixel_ratio: Option<f64>,
+    ///stitched pages are cut off below this many css pixels.
+    pub max_height: u32,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        ScreenshotConfig {
+            mode: ScreenshotMode::FullPage,
+            viewport: None,
+            device_pixel_ratio: None,
+            max_height: 20_000,
+        }
+    }
+}
+
+///GET /session/{id}/moz/screenshot/full, geckodriver's full page screenshot.
+#[derive(Debug)]
+struct FullPageScreenshot;
+
+impl WebDriverCompatibleCommand for FullPageScreenshot {
+    fn endpoint(
+        &self,
+        base_url: &url::Url,
+        session_id: Option<&str>,
+    ) -> Result<url::Url, url::ParseError> {
+        base_url.join(&format!(
+            "session/{}/moz/screenshot/full",
+            session_id.unwrap_or_default()
+        ))
+    }
+
+    fn method_and_body(&self, _request_url: &url::Url) -> (http::Method, Option<String>) {
+        (http::Method::GET, None)
+    }
+}
+
+///public function:
+/// png of the page the browser is on, as config.mode says.
+/// full pages come from firefox's own endpoint, other browsers
+/// get scrolled through and the viewport shots stitched together.
+pub async fn capture(fclient: &Client, config: &ScreenshotConfig) -> Result<Vec<u8>, Error> {
+    match config.mode {
+        ScreenshotMode::Viewport => Ok(fclient.screenshot().await?),
+        ScreenshotMode::FullPage => match fclient.issue_cmd(FullPageScreenshot).await {
+            Ok(Value::String(encoded)) => STANDARD
+                .decode(encoded)
+                .map_err(|e| Error::Parse(format!("full page screenshot: {}", e))),
+            _ => scroll_and_stitch(fclient, config.max_height).await,
+        },
+    }
+}
+
+///public function:
+/// resizes the window so the viewport (window.innerWidth/innerHeight)
+/// ends up width x height css pixels.
+pub async fn set_viewport(fclient: &Client, width: u32, height: u32) -> Result<(), Error> {
+    let (outer_width, outer_height) = fclient.get_window_size().await?;
+    let inner = fclient
+        .execute("return [window.innerWidth, window.innerHeight]", vec![])
+        .await?;
+    let inner_width = inner[0].as_u64().unwrap_or(outer_width);
+    let inner_height = inner[1].as_u64().unwrap_or(outer_height);
+    //the window is bigger than the viewport by the browser chrome
+    fclient
+        .set_window_size(
+            width + outer_width.saturating_sub(inner_width) as u32,
+            height + outer_height.saturating_sub(inner_height) as u32,
+        )
+        .await?;
+    Ok(())
+}
+
+///public function:
+/// adds the firefox pref for the device pixel ratio to capabilities
+/// from web_archiver::get_capabilities.
+pub fn set_device_pixel_ratio(capabilities: &mut Map<String, Value>, ratio: f64) {
+    if let Some(Value::Object(options)) = capabilities.get_mut("moz:firefoxOptions") {
+        let prefs = options
+            .entry("prefs")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(prefs) = prefs {
+            prefs.insert(
+                "layout.css.devPixelsPerPx".to_string(),
+                Value::String(ratio.to_string()),
+            );
+        }
+    }
+}
+
+///scrolls down one viewport at a time, screenshotting each,
+/// and puts them together. fixed headers show up once per viewport.
+async fn scroll_and_stitch(fclient: &Client, max_height: u32) -> Result<Vec<u8>, Error> {
+    let metrics = fclient
+        .execute(
+            "return [window.innerHeight, Math.max(document.documentElement.scrollHeight, \
+             document.body ? document.body.scrollHeight : 0)]",
+            vec![],
+        )
+        .await?;
+    let viewport_height = metrics[0].as_u64().unwrap_or(1).max(1) as u32;
+    let page_height = (metrics[1].as_u64().unwrap_or(0) as u32).min(max_height);
+
+    //(scroll offset in css pixels, png)
+    let mut shots: Vec<(u32, Vec<u8>)> = vec![];
+    let mut offset = 0;
+    loop {
+        let scrolled = fclient
+            .execute(
+                "window.scrollTo(0, arguments[0]); return window.scrollY;",
+                vec![Value::from(offset)],
+            )
+            .await?
+            .as_f64()
+            .unwrap_or(offset as f64) as u32;
+        //let lazy content and the scroll settle
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        shots.push((scrolled, fclient.screenshot().await?));
+        offset += viewport_height;
+        //the browser stops scrolling at the bottom
+        if offset >= page_height || scrolled + viewport_height >= page_height {
+            break;
+        }
+    }
+    let _ = fclient.execute("window.scrollTo(0, 0)", vec![]).await;
+
+    stitch(&shots, viewport_height, page_height.max(viewport_height))
+}
+
+///public function:
+/// one png out of viewport screenshots taken at the given scroll offsets
+/// (css pixels). the device pixel ratio comes from how many pixels tall a
+/// shot is compared to viewport_height, page_height is in css pixels too.
+pub fn stitch(
+    shots: &[(u32, Vec<u8>)],
+    viewport_height: u32,
+    page_height: u32,
+) -> Result<Vec<u8>, Error> {
+    let decoded = shots
+        .iter()
+        .map(|(offset, png)| {
+            image::load_from_memory(png)
+                .map(|image| (*offset, image.to_rgba8()))
+                .map_err(|e| Error::Parse(format!("screenshot: {}", e)))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let first = match decoded.first() {
+        Some((_, first)) => first,
+        None => return Err(Error::Parse("no screenshots to stitch".to_string())),
+    };
+    let scale = first.height() as f64 / viewport_height as f64;
+    let width = first.width();
+    let height = (page_height as f64 * scale).round() as u32;
+
+    let mut canvas = RgbaImage::new(width, height);
+    for (offset, shot) in &decoded {
+        let y = (*offset as f64 * scale).round() as i64;
+        imageops::replace(&mut canvas, shot, 0, y);
+    }
+
+    let mut png: Vec<u8> = vec![];
+    DynamicImage::ImageRgba8(canvas)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .map_err(|e| Error::Parse(format!("screenshot: {}", e)))?;
+    Ok(png)
+}
@@ -0,0 +1,218 @@
+use crate::client::*;
+use crate::css::{css_references, rewrite_css};
+use crate::error::Error;
+use crate::html::{HtmlRecord, ResourceKind, IMAGE_ATTRIBUTES, RESOURCE_ATTRIBUTES};
+use crate::mime::guess_mime;
+use crate::rewrite::DomRewriter;
+use crate::web_archiver::{snapshot_directory, write_metadata};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use url::Url;
+
+///how deep @import chains are followed before the rest is left pointing online.
+const MAX_IMPORT_DEPTH: usize = 5;
+
+/// public function:
+/// writes the page as one self-contained single_file.html in the snapshot directory.
+/// stylesheets and scripts are inlined, images, icons, fonts and css assets
+/// become base64 data uris. media, embeds and anything that could not be
+/// downloaded keep pointing at the absolute online url.
+/// returns the path to single_file.html
+pub async fn save_single_file(
+    client: &HttpClient,
+    html_record: HtmlRecord,
+    base_path: &str,
+) -> Result<String, Error> {
+    let directory = snapshot_directory(client, &html_record, base_path)?;
+    let mut inliner = Inliner::new(client);
+    //original attribute value -> data uri (or absolute url)
+    let mut image_map: HashMap<String, String> = HashMap::new();
+    let mut resource_map: HashMap<String, String> = HashMap::new();
+    let mut style_map: HashMap<String, String> = HashMap::new();
+    //original attribute value -> file contents to put inline
+    let mut css_contents: HashMap<String, String> = HashMap::new();
+    let mut js_contents: HashMap<String, String> = HashMap::new();
+    let mut link_map: HashMap<String, String> = HashMap::new();
+
+    fs::create_dir_all(directory.clone())?;
+
+    if let Some(image_links) = html_record.get_image_links() {
+        for (raw, link) in image_links {
+            let uri = inliner.data_uri(&link).await.unwrap_or(link);
+            image_map.insert(raw, uri);
+        }
+    }
+
+    if let Some(css_links) = html_record.get_css_links() {
+        for (raw, link) in css_links {
+            match fetch_string_resource(client, &link).await {
+                Ok(css) => {
+                    let css = inliner.inline_css(&css, &link, 0).await;
+                    css_contents.insert(raw, css);
+                }
+                Err(_) => {
+                    link_map.insert(raw, link);
+                }
+            }
+        }
+    }
+
+    if let Some(js_links) = html_record.get_js_links() {
+        for (raw, link) in js_links {
+            match fetch_string_resource(client, &link).await {
+                Ok(js) => {
+                    js_contents.insert(raw, js);
+                }
+                Err(_) => {
+                    link_map.insert(raw, link);
+                }
+            }
+        }
+    }
+
+    if let Some(resource_links) = html_record.get_resource_links() {
+        for (raw, link, kind) in resource_links {
+            let uri = match kind {
+                ResourceKind::Icon
+                | ResourceKind::Image
+                | ResourceKind::Font
+                | ResourceKind::Manifest
+                | ResourceKind::Track => inliner.data_uri(&link).await.unwrap_or(link),
+                //too big or only a hint, the online copy is good enough
+                _ => link,
+            };
+            resource_map.insert(raw, uri);
+        }
+    }
+
+    if let Some(styles) = html_record.get_inline_styles() {
+        for style in styles {
+            let inlined = inliner.inline_css(&style, &html_record.origin, 0).await;
+            if inlined != style {
+                style_map.insert(style, inlined);
+            }
+        }
+    }
+
+    let mut rewriter = DomRewriter::new(&html_record);
+    for (tag, attribute, is_srcset) in IMAGE_ATTRIBUTES {
+        if is_srcset {
+            rewriter.rewrite_srcset(tag, attribute, &image_map)?;
+        } else {
+            rewriter.rewrite_attribute(tag, attribute, &image_map)?;
+        }
+    }
+    rewriter.promote_lazy(&image_map)?;
+    rewriter.inline_stylesheets(&css_contents)?;
+    rewriter.inline_scripts(&js_contents)?;
+    rewriter.rewrite_attribute("link[rel~=stylesheet]", "href", &link_map)?;
+    rewriter.rewrite_attribute("script", "src", &link_map)?;
+    for (tag, attribute, _) in RESOURCE_ATTRIBUTES {
+        rewriter.rewrite_attribute(tag, attribute, &resource_map)?;
+    }
+    rewriter.rewrite_styles(&style_map)?;
+    rewriter.declare_utf8()?;
+    let body = rewriter.html();
+
+    let fqn_html = format!("{}/single_file.html", directory);
+    let mut file_html = File::create(fqn_html.clone())?;
+    file_html.write_all(body.as_bytes())?;
+    write_metadata(&directory, &html_record)?;
+
+    Ok(fqn_html)
+}
+
+///downloads assets and turns them into data uris,
+/// every url is only fetched once per page.
+pub struct Inliner<'a> {
+    client: &'a HttpClient,
+    ///absolute url -> data uri, none when the download failed
+    cache: HashMap<String, Option<String>>,
+}
+
+impl<'a> Inliner<'a> {
+    pub fn new(client: &'a HttpClient) -> Self {
+        Inliner {
+            client,
+            cache: HashMap::new(),
+        }
+    }
+
+    ///public method:
+    /// base64 data uri for the asset at url, the mime type comes from
+    /// the content type, the magic bytes or the extension, in that order.
+    pub async fn data_uri(&mut self, url: &str) -> Option<String> {
+        if let Some(cached) = self.cache.get(url) {
+            return cached.clone();
+        }
+        let uri = match fetch_asset(self.client, url).await {
+            Ok(asset) => Some(data_uri(
+                &guess_mime(url, asset.content_type.as_deref(), &asset.bytes),
+                &asset.bytes,
+            )),
+            Err(_) => None,
+        };
+        self.cache.insert(url.to_string(), uri.clone());
+        uri
+    }
+
+    ///public method:
+    /// every url() in the css becomes a data uri and every @import a
+    /// data:text/css uri holding the (inlined) imported sheet.
+    /// whatever cannot be fetched is made absolute against base_url instead.
+    pub fn inline_css<'b>(
+        &'b mut self,
+        css: &'b str,
+        base_url: &'b str,
+        depth: usize,
+    ) -> Pin<Box<dyn Future<Output = String> + Send + 'b>>
+    where
+        'a: 'b,
+    {
+        Box::pin(async move {
+            let base = match Url::parse(base_url) {
+                Ok(base) => base,
+                Err(_) => return css.to_string(),
+            };
+            let mut replacements: HashMap<String, String> = HashMap::new();
+
+            for reference in css_references(css) {
+                let absolute = match base.join(&reference.url) {
+                    Ok(url) => url.to_string(),
+                    Err(_) => continue,
+                };
+                let uri = if reference.import {
+                    if depth >= MAX_IMPORT_DEPTH {
+                        None
+                    } else {
+                        match fetch_string_resource(self.client, &absolute).await {
+                            Ok(imported) => {
+                                let imported =
+                                    self.inline_css(&imported, &absolute, depth + 1).await;
+                                Some(data_uri("text/css", imported.as_bytes()))
+                            }
+                            Err(_) => None,
+                        }
+                    }
+                } else {
+                    self.data_uri(&absolute).await
+                };
+                replacements.insert(reference.url, uri.unwrap_or(absolute));
+            }
+
+            rewrite_css(css, &replacements)
+        })
+    }
+}
+
+///public function:
+/// "data:{mime};base64,..." for the bytes.
+pub fn data_uri(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, STANDARD.encode(bytes))
+}
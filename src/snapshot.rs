@@ -0,0 +1,233 @@
+use crate::error::Error;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+///one resource the page referenced and what became of it.
+/// local_path is relative to the snapshot directory and only set when it was saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceEntry {
+    pub url: String,
+    pub local_path: Option<String>,
+    pub mime: Option<String>,
+    ///size of the archived file in bytes.
+    pub size: Option<u64>,
+    ///hex sha-256 of the archived file.
+    pub sha256: Option<String>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl ResourceEntry {
+    ///an entry for a resource written to local_path.
+    pub fn saved(url: &str, local_path: &str, mime: &str, status: u16, bytes: &[u8]) -> Self {
+        ResourceEntry {
+            url: url.to_string(),
+            local_path: Some(local_path.to_string()),
+            mime: Some(mime.to_string()),
+            size: Some(bytes.len() as u64),
+            sha256: Some(sha256_hex(bytes)),
+            status: Some(status),
+            error: None,
+        }
+    }
+
+    ///an entry for a resource that could not be archived.
+    pub fn failed(url: &str, error: &Error) -> Self {
+        let status = match error {
+            Error::HttpStatus { status, .. } => Some(*status),
+            _ => None,
+        };
+        ResourceEntry {
+            url: url.to_string(),
+            local_path: None,
+            mime: None,
+            size: None,
+            sha256: None,
+            status,
+            error: Some(error.to_string()),
+        }
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.local_path.is_some()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "url": self.url,
+            "local_path": self.local_path,
+            "mime": self.mime,
+            "size": self.size,
+            "sha256": self.sha256,
+            "status": self.status,
+            "error": self.error,
+        })
+    }
+}
+
+///what save_page wrote for one page, also written out as manifest.json.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    ///random id of the capture, also the end of the directory name.
+    pub id: String,
+    pub url: String,
+    pub date_time: DateTime<Utc>,
+    pub directory: String,
+    ///path to index.html (archive.warc.gz for save_warc)
+    pub index_path: String,
+    ///path to screenshot.png when one was taken.
+    pub screenshot_path: Option<String>,
+    ///path to page.pdf when the page was printed.
+    pub pdf_path: Option<String>,
+    ///every discovered resource, saved or not, in the order they were handled.
+    pub resources: Vec<ResourceEntry>,
+}
+
+impl Snapshot {
+    ///resources that were archived.
+    pub fn saved(&self) -> impl Iterator<Item = &ResourceEntry> {
+        self.resources.iter().filter(|entry| entry.is_saved())
+    }
+
+    ///resources that could not be archived.
+    pub fn failed(&self) -> impl Iterator<Item = &ResourceEntry> {
+        self.resources.iter().filter(|entry| !entry.is_saved())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "url": self.url,
+            "date_time": self.date_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "index": Path::new(&self.index_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("index.html"),
+            "screenshot": self.screenshot_path.as_ref().map(|_| "screenshot.png"),
+            "pdf": self.pdf_path.as_ref().map(|_| "page.pdf"),
+            "resources": self.resources.iter().map(ResourceEntry::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    ///writes manifest.json into the snapshot directory.
+    pub fn write_manifest(&self) -> Result<String, Error> {
+        let path = format!("{}/manifest.json", self.directory);
+        let manifest = serde_json::to_string_pretty(&self.to_json())
+            .map_err(|e| Error::Parse(e.to_string()))?;
+        File::create(&path)?.write_all(manifest.as_bytes())?;
+        Ok(path)
+    }
+}
+
+///what happened to one url handed to an archiver or found by a crawler.
+/// error is set (and snapshot is none) when nothing could be archived.
+#[derive(Debug)]
+pub struct ArchiveResult {
+    pub source_url: String,
+    ///where the page ended up after redirects, when known.
+    pub final_url: Option<String>,
+    pub directory: Option<String>,
+    ///the capture time the snapshot directory is named after.
+    pub timestamp: Option<DateTime<Utc>>,
+    pub assets_saved: usize,
+    pub assets_failed: usize,
+    pub screenshot_path: Option<String>,
+    pub pdf_path: Option<String>,
+    ///things that went wrong without stopping the archive (failed assets, no screenshot...).
+    pub warnings: Vec<String>,
+    pub error: Option<Error>,
+    pub snapshot: Option<Snapshot>,
+}
+
+impl ArchiveResult {
+    ///result for a saved snapshot, every failed resource becomes a warning.
+    pub fn archived(
+        source_url: &str,
+        final_url: Option<String>,
+        snapshot: Snapshot,
+        mut warnings: Vec<String>,
+    ) -> Self {
+        for failed in snapshot.failed() {
+            warnings.push(format!(
+                "could not archive {}: {}",
+                failed.url,
+                failed.error.as_deref().unwrap_or("unknown error")
+            ));
+        }
+        ArchiveResult {
+            source_url: source_url.to_string(),
+            final_url,
+            directory: Some(snapshot.directory.clone()),
+            timestamp: Some(snapshot.date_time),
+            assets_saved: snapshot.saved().count(),
+            assets_failed: snapshot.failed().count(),
+            screenshot_path: snapshot.screenshot_path.clone(),
+            pdf_path: snapshot.pdf_path.clone(),
+            warnings,
+            error: None,
+            snapshot: Some(snapshot),
+        }
+    }
+
+    ///result for a url that could not be archived at all.
+    pub fn failed(source_url: &str, error: Error) -> Self {
+        ArchiveResult {
+            source_url: source_url.to_string(),
+            final_url: None,
+            directory: None,
+            timestamp: None,
+            assets_saved: 0,
+            assets_failed: 0,
+            screenshot_path: None,
+            pdf_path: None,
+            warnings: vec![],
+            error: Some(error),
+            snapshot: None,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    ///path to index.html when the page was archived.
+    pub fn index_path(&self) -> Option<&str> {
+        self.snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.index_path.as_str())
+    }
+}
+
+///entries keyed by url, a url is only listed once.
+/// a later success replaces an earlier failure for the same url.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub resources: Vec<ResourceEntry>,
+}
+
+impl Manifest {
+    pub fn push(&mut self, entry: ResourceEntry) {
+        match self
+            .resources
+            .iter_mut()
+            .find(|existing| existing.url == entry.url)
+        {
+            Some(existing) if !existing.is_saved() && entry.is_saved() => *existing = entry,
+            Some(_) => {}
+            None => self.resources.push(entry),
+        }
+    }
+}
+
+///public function:
+/// lowercase hex sha-256 of the bytes.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
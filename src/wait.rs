@@ -0,0 +1,184 @@
+use crate::error::Error;
+use fantoccini::error::CmdError;
+use fantoccini::{Client, Locator};
+use regex::Regex;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+///what the browser waits for after navigating, before the source is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaitStrategy {
+    ///read the source as soon as the navigation returns.
+    Nothing,
+    ///document.readyState is "complete" (load event fired).
+    ReadyState,
+    ///an element matching the css selector is in the document.
+    Selector(String),
+    ///no new resources (performance.getEntriesByType("resource")) for this long,
+    /// for pages that keep fetching after the load event.
+    NetworkIdle(Duration),
+    ///a fixed pause.
+    Delay(Duration),
+    ///each of these in turn.
+    All(Vec<WaitStrategy>),
+}
+
+///how FantocciniFetcher waits for pages, part of fetcher::BrowserConfig.
+#[derive(Debug, Clone)]
+pub struct WaitConfig {
+    ///used for every url no override matches.
+    pub default: WaitStrategy,
+    ///url pattern -> strategy, the first matching pattern wins.
+    pub overrides: Vec<(Regex, WaitStrategy)>,
+    ///the most a page is waited for, all steps together.
+    /// a page that is not ready by then is archived anyway, with a warning.
+    pub timeout: Duration,
+    ///how often readyState, the selector or the resource count are checked.
+    pub poll_interval: Duration,
+}
+
+impl Default for WaitConfig {
+    fn default() -> Self {
+        WaitConfig {
+            default: WaitStrategy::ReadyState,
+            overrides: vec![],
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+impl WaitConfig {
+    ///public method:
+    /// the strategy for url, the first override whose pattern matches or the default.
+    pub fn strategy_for(&self, url: &str) -> &WaitStrategy {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(url))
+            .map(|(_, strategy)| strategy)
+            .unwrap_or(&self.default)
+    }
+
+    ///public method:
+    /// waits on the page the browser is on with the strategy for url,
+    /// Error::Timeout when it took longer than timeout.
+    pub async fn wait_for(&self, fclient: &Client, url: &str) -> Result<(), Error> {
+        let deadline = Instant::now() + self.timeout;
+        self.run(fclient, self.strategy_for(url), deadline).await
+    }
+
+    async fn run(
+        &self,
+        fclient: &Client,
+        strategy: &WaitStrategy,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        match strategy {
+            WaitStrategy::Nothing => Ok(()),
+            WaitStrategy::ReadyState => self.ready_state(fclient, deadline).await,
+            WaitStrategy::Selector(selector) => self.selector(fclient, selector, deadline).await,
+            WaitStrategy::NetworkIdle(quiet) => self.network_idle(fclient, *quiet, deadline).await,
+            WaitStrategy::Delay(delay) => self.delay(*delay, deadline).await,
+            WaitStrategy::All(strategies) => {
+                for strategy in strategies {
+                    //boxed, All can nest
+                    Box::pin(self.run(fclient, strategy, deadline)).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn ready_state(&self, fclient: &Client, deadline: Instant) -> Result<(), Error> {
+        loop {
+            let state = fclient
+                .execute("return document.readyState", vec![])
+                .await?;
+            if state == Value::String("complete".to_string()) {
+                return Ok(());
+            }
+            self.pause(deadline, "document.readyState to be complete")
+                .await?;
+        }
+    }
+
+    async fn selector(
+        &self,
+        fclient: &Client,
+        selector: &str,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        let result = fclient
+            .wait()
+            .at_most(deadline.saturating_duration_since(Instant::now()))
+            .every(self.poll_interval)
+            .for_element(Locator::Css(selector))
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(CmdError::WaitTimeout) => Err(Error::Timeout(format!(
+                "no element matching {} after {:?}",
+                selector, self.timeout
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    ///counts the performance api resource entries until the count
+    /// has not changed for quiet. the resource timing buffer is made big enough
+    /// first, at the default 250 entries a busy page would stop counting and look idle.
+    async fn network_idle(
+        &self,
+        fclient: &Client,
+        quiet: Duration,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        fclient
+            .execute("performance.setResourceTimingBufferSize(100000)", vec![])
+            .await?;
+        let mut last_count: Option<u64> = None;
+        let mut last_change = Instant::now();
+        loop {
+            let count = fclient
+                .execute(
+                    "return performance.getEntriesByType('resource').length",
+                    vec![],
+                )
+                .await?
+                .as_u64();
+            if count != last_count {
+                last_count = count;
+                last_change = Instant::now();
+            } else if last_change.elapsed() >= quiet {
+                return Ok(());
+            }
+            self.pause(deadline, "the network to go quiet").await?;
+        }
+    }
+
+    ///a fixed pause, cut short with Error::Timeout when it would run past the deadline.
+    pub(crate) async fn delay(&self, delay: Duration, deadline: Instant) -> Result<(), Error> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if delay <= left {
+            tokio::time::sleep(delay).await;
+            return Ok(());
+        }
+        tokio::time::sleep(left).await;
+        Err(Error::Timeout(format!(
+            "a {:?} delay is longer than the {:?} timeout",
+            delay, self.timeout
+        )))
+    }
+
+    ///sleeps one poll interval, Error::Timeout once the deadline has passed.
+    async fn pause(&self, deadline: Instant, waiting_for: &str) -> Result<(), Error> {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout(format!(
+                "waited {:?} for {}",
+                self.timeout, waiting_for
+            )));
+        }
+        tokio::time::sleep(self.poll_interval).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use crate::client::RawResponse;
+use crate::error::Error;
+use chrono::{SecondsFormat, Utc};
+use flate2::{write::GzEncoder, Compression};
+use rand::Rng;
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::Write;
+
+pub static WARC_VERSION: &str = "WARC/1.1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarcType {
+    Warcinfo,
+    Request,
+    Response,
+    Resource,
+    Metadata,
+}
+
+impl WarcType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarcType::Warcinfo => "warcinfo",
+            WarcType::Request => "request",
+            WarcType::Response => "response",
+            WarcType::Resource => "resource",
+            WarcType::Metadata => "metadata",
+        }
+    }
+}
+
+///a single warc record,
+/// the mandatory headers (type, id, date, length) are filled in by the writer.
+#[derive(Debug, Clone)]
+pub struct WarcRecord {
+    pub warc_type: WarcType,
+    pub target_uri: Option<String>,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub block: Vec<u8>,
+}
+
+impl WarcRecord {
+    pub fn new(warc_type: WarcType, content_type: &str, block: Vec<u8>) -> WarcRecord {
+        WarcRecord {
+            warc_type,
+            target_uri: None,
+            content_type: content_type.to_string(),
+            headers: vec![],
+            block,
+        }
+    }
+}
+
+///writes gzip-per-record warc 1.1 files,
+/// every record is its own gzip member so tools can seek straight to it.
+pub struct WarcWriter {
+    file: File,
+    pub path: String,
+    pub warcinfo_id: String,
+}
+
+impl WarcWriter {
+    ///creates the file at path and writes the warcinfo record.
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let file = File::create(path)?;
+        let mut writer = WarcWriter {
+            file,
+            path: path.to_string(),
+            warcinfo_id: String::new(),
+        };
+
+        let file_name = path.rsplit('/').next().unwrap_or(path).to_string();
+        let fields = format!(
+            "software: lolchive/{}\r\nformat: WARC File Format 1.1\r\nconformsTo: http://iipc.github.io/warc-specifications/specifications/warc-format/warc-1.1/\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        let mut info = WarcRecord::new(
+            WarcType::Warcinfo,
+            "application/warc-fields",
+            fields.into_bytes(),
+        );
+        info.headers.push(("WARC-Filename".to_string(), file_name));
+        writer.warcinfo_id = writer.write_record(info)?;
+
+        Ok(writer)
+    }
+
+    ///writes a record and returns its WARC-Record-ID.
+    pub fn write_record(&mut self, record: WarcRecord) -> Result<String, Error> {
+        let record_id = new_record_id();
+        let mut head = format!(
+            "{}\r\nWARC-Type: {}\r\nWARC-Record-ID: {}\r\nWARC-Date: {}\r\n",
+            WARC_VERSION,
+            record.warc_type.as_str(),
+            record_id,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        if let Some(target_uri) = &record.target_uri {
+            head.push_str(&format!("WARC-Target-URI: {}\r\n", target_uri));
+        }
+        if record.warc_type != WarcType::Warcinfo && !self.warcinfo_id.is_empty() {
+            head.push_str(&format!("WARC-Warcinfo-ID: {}\r\n", self.warcinfo_id));
+        }
+        for (name, value) in &record.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if record.warc_type != WarcType::Warcinfo {
+            head.push_str(&format!(
+                "WARC-Block-Digest: {}\r\n",
+                sha1_digest(&record.block)
+            ));
+        }
+        head.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            record.content_type,
+            record.block.len()
+        ));
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(head.as_bytes())?;
+        encoder.write_all(&record.block)?;
+        encoder.write_all(b"\r\n\r\n")?;
+        self.file.write_all(&encoder.finish()?)?;
+
+        Ok(record_id)
+    }
+
+    ///writes the request and response records for an http exchange,
+    /// one pair per redirect hop first, returns the id of the final response record.
+    pub fn write_exchange(&mut self, raw: &RawResponse) -> Result<String, Error> {
+        for hop in &raw.redirects {
+            self.write_hop(hop)?;
+        }
+        self.write_hop(raw)
+    }
+
+    fn write_hop(&mut self, raw: &RawResponse) -> Result<String, Error> {
+        let mut response = WarcRecord::new(
+            WarcType::Response,
+            "application/http;msgtype=response",
+            raw.response_block(),
+        );
+        response.target_uri = Some(raw.url.clone());
+        response
+            .headers
+            .push(("WARC-Payload-Digest".to_string(), sha1_digest(&raw.body)));
+        let response_id = self.write_record(response)?;
+
+        let mut request = WarcRecord::new(
+            WarcType::Request,
+            "application/http;msgtype=request",
+            raw.request_block(),
+        );
+        request.target_uri = Some(raw.url.clone());
+        request
+            .headers
+            .push(("WARC-Concurrent-To".to_string(), response_id.clone()));
+        self.write_record(request)?;
+
+        Ok(response_id)
+    }
+
+    ///writes a resource record, used for content that did not come
+    /// straight off the network (rendered dom, screenshots).
+    pub fn write_resource(
+        &mut self,
+        target_uri: &str,
+        content_type: &str,
+        block: Vec<u8>,
+    ) -> Result<String, Error> {
+        let payload_digest = sha1_digest(&block);
+        let mut resource = WarcRecord::new(WarcType::Resource, content_type, block);
+        resource.target_uri = Some(target_uri.to_string());
+        resource
+            .headers
+            .push(("WARC-Payload-Digest".to_string(), payload_digest));
+        self.write_record(resource)
+    }
+
+    ///writes a metadata record about the record with concurrent_to id.
+    pub fn write_metadata(
+        &mut self,
+        target_uri: &str,
+        concurrent_to: &str,
+        fields: Vec<(String, String)>,
+    ) -> Result<String, Error> {
+        let mut block = String::new();
+        for (name, value) in fields {
+            block.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        let mut metadata = WarcRecord::new(
+            WarcType::Metadata,
+            "application/warc-fields",
+            block.into_bytes(),
+        );
+        metadata.target_uri = Some(target_uri.to_string());
+        metadata
+            .headers
+            .push(("WARC-Concurrent-To".to_string(), concurrent_to.to_string()));
+        self.write_record(metadata)
+    }
+}
+
+///random (version 4) uuid in the urn form warc expects.
+fn new_record_id() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+///public function:
+/// "sha1:" and the base32 sha1 of bytes, the form warc digests are usually written in.
+pub fn sha1_digest(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let hash = Sha1::digest(bytes);
+    //160 bits is exactly 32 base32 characters, no padding
+    let mut encoded = String::with_capacity(32);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in hash.iter() {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    format!("sha1:{}", encoded)
+}
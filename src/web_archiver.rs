@@ -1,246 +1,899 @@
+use crate::charset::decode_css;
 use crate::client::*;
-use crate::html::HtmlRecord;
-use fantoccini::{Client, ClientBuilder};
-use image;
-use rand::{distributions::Alphanumeric, Rng};
+use crate::css::{css_asset_directory, css_references, rewrite_css};
+use crate::error::Error;
+use crate::fetcher::{
+    BrowserConfig, FantocciniFetcher, FetchedPage, HybridFetcher, PageFetcher, ReqwestFetcher,
+};
+use crate::html::{HtmlRecord, ResourceKind, IMAGE_ATTRIBUTES, RESOURCE_ATTRIBUTES};
+use crate::mhtml::save_mhtml;
+use crate::mime::{guess_mime, image_extension};
+use crate::objects::ObjectStore;
+use crate::render::RenderRules;
+use crate::rewrite::DomRewriter;
+use crate::sanitize::{
+    encode_path, numbered_file_name, sanitize_component, sanitize_relative_path,
+};
+use crate::single_file::save_single_file;
+use crate::snapshot::{sha256_hex, ArchiveResult, Manifest, ResourceEntry, Snapshot};
+use crate::warc::WarcWriter;
 use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::time::Duration;
 use url::Url;
 
-pub struct FantocciniArchiver {
-    pub fclient: Client,
+///archives pages fetched by any PageFetcher.
+/// BasicArchiver (reqwest) and FantocciniArchiver (webdriver) are the two
+/// that come with the crate, Archiver::with_fetcher takes your own.
+pub struct Archiver<F: PageFetcher> {
+    pub fetcher: F,
 }
 
-impl FantocciniArchiver {
-    pub async fn new(connection_string: &str) -> Result<Self, String> {
-        let client = ClientBuilder::native()
-            .capabilities(get_capabilities())
-            .connect(connection_string)
-            .await
-            .unwrap_or_else(|_| panic!("failed to connect to WebDriver on {}", connection_string));
+pub type BasicArchiver = Archiver<ReqwestFetcher>;
+pub type FantocciniArchiver = Archiver<FantocciniFetcher>;
+pub type HybridArchiver = Archiver<HybridFetcher>;
 
-        Ok(FantocciniArchiver { fclient: client })
+impl<F: PageFetcher> Archiver<F> {
+    pub fn with_fetcher(fetcher: F) -> Self {
+        Archiver { fetcher }
     }
 
-    pub async fn create_archive(&self, url: &str, path: &str) -> Result<String, String> {
-        if self.fclient.goto(url).await.is_err() {
-            return Err(format!("could not go to url {}", url));
-        }
-        let _ = self.fclient.wait().at_most(Duration::from_secs(10));
-
-        let body = self
-            .fclient
-            .source()
-            .await
-            .unwrap_or_else(|_| panic!("can't carse to html {}", url));
-
-        let body = replace_encoded_chars(body);
+    ///the client assets are downloaded with.
+    pub fn client(&self) -> &HttpClient {
+        self.fetcher.client()
+    }
 
-        let record = HtmlRecord::new(url.to_string(), body);
+    pub async fn create_archive(&self, url: &str, path: &str) -> Result<ArchiveResult, Error> {
+        let page = self.fetcher.fetch(url).await?;
 
-        if let Ok(screen_shot) = self.fclient.screenshot().await {
-            match save_page(record, path, Some(screen_shot)).await {
-                Ok(archive_path) => Ok(archive_path),
-                Err(e) => Err(e),
-            }
-        } else {
-            match save_page(record, path, None).await {
-                Ok(archive_path) => Ok(archive_path),
-                Err(e) => Err(e),
-            }
-        }
+        archive_page(self.fetcher.client(), page, path).await
     }
 
+    ///one result per url, in order. a url that fails does not stop the
+    /// others, its result carries the error instead.
     pub async fn create_archives(
         &self,
         urls: Vec<&str>,
         path: &str,
-    ) -> Result<Vec<String>, String> {
-        let mut path_vector: Vec<String> = vec![];
+    ) -> Result<Vec<ArchiveResult>, Error> {
+        let mut results: Vec<ArchiveResult> = vec![];
 
         for url in urls {
-            if self.fclient.goto(url).await.is_err() {
-                return Err(format!("could not go to url {}", url));
-            }
-            let _ = self.fclient.wait().at_most(Duration::from_secs(10));
+            let result = match self.create_archive(url, path).await {
+                Ok(result) => result,
+                Err(e) => ArchiveResult::failed(url, e),
+            };
+            results.push(result);
+        }
+        Ok(results)
+    }
 
-            let body: String;
+    ///same as create_archive but writes a single archive.warc.gz
+    /// into the snapshot directory instead of the loose file tree.
+    /// the page is stored as the http exchange when the fetcher keeps one
+    /// (reqwest), as a resource record of the rendered source otherwise.
+    pub async fn create_warc(&self, url: &str, path: &str) -> Result<String, Error> {
+        let page = self.fetcher.fetch_exchange(url).await?;
+
+        save_warc(
+            self.fetcher.client(),
+            page.record,
+            path,
+            page.response,
+            page.screenshot,
+        )
+        .await
+    }
 
-            if let Ok(str) = self.fclient.source().await {
-                body = replace_encoded_chars(str);
-            } else {
-                continue;
-            }
+    ///same as create_archive but writes one self-contained single_file.html
+    /// with everything inlined instead of the loose file tree.
+    pub async fn create_single_file(&self, url: &str, path: &str) -> Result<String, Error> {
+        let page = self.fetcher.fetch(url).await?;
 
-            let record = HtmlRecord::new(url.to_string(), body);
+        save_single_file(self.fetcher.client(), page.record, path).await
+    }
 
-            if let Ok(image) = self.fclient.screenshot().await {
-                if let Ok(archive_path) = save_page(record, path, Some(image)).await {
-                    path_vector.push(archive_path);
-                }
-            } else if let Ok(archive_path) = save_page(record, path, None).await {
-                path_vector.push(archive_path);
-            }
-        }
-        Ok(path_vector)
+    ///same as create_archive but packages the page and its assets
+    /// into one archive.mhtml (multipart/related) file.
+    pub async fn create_mhtml(&self, url: &str, path: &str) -> Result<String, Error> {
+        let page = self.fetcher.fetch(url).await?;
+
+        save_mhtml(self.fetcher.client(), page.record, path).await
     }
-    pub async fn close(self) -> Result<(), String> {
-        if self.fclient.close().await.is_ok() {
-            Ok(())
-        } else {
-            Err(
-                "closing the archiver did not work, exit the program and restart the geckodriver"
-                    .to_string(),
-            )
-        }
+}
+
+impl Archiver<ReqwestFetcher> {
+    pub fn new(config: ArchiverConfig) -> Result<Self, Error> {
+        Ok(Archiver::with_fetcher(ReqwestFetcher::new(config)?))
     }
 }
 
-pub struct BasicArchiver {}
+impl Default for Archiver<ReqwestFetcher> {
+    fn default() -> Self {
+        Archiver::with_fetcher(ReqwestFetcher::default())
+    }
+}
 
-impl BasicArchiver {
-    pub async fn create_archive(url: &str, path: &str) -> Result<String, String> {
-        let record = fetch_html_record(url)
-            .await
-            .unwrap_or_else(|_| panic!("fetch_html_record failed \n url {}", url));
+impl Archiver<FantocciniFetcher> {
+    pub async fn new(connection_string: &str) -> Result<Self, Error> {
+        Ok(Archiver::with_fetcher(
+            FantocciniFetcher::new(connection_string).await?,
+        ))
+    }
+
+    pub async fn with_config(
+        connection_string: &str,
+        config: ArchiverConfig,
+        browser_config: BrowserConfig,
+    ) -> Result<Self, Error> {
+        Ok(Archiver::with_fetcher(
+            FantocciniFetcher::with_config(connection_string, config, browser_config).await?,
+        ))
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.fetcher.close().await
+    }
+}
 
-        match save_page(record, path, None).await {
-            Ok(archive_path) => Ok(archive_path),
-            Err(e) => Err(e),
+///save_page wrapped up as an ArchiveResult,
+/// a page that did not answer 2xx is archived but warned about.
+pub(crate) async fn archive_page(
+    client: &HttpClient,
+    mut page: FetchedPage,
+    base_path: &str,
+) -> Result<ArchiveResult, Error> {
+    let mut warnings = std::mem::take(&mut page.warnings);
+    let source_url = page.record.origin.clone();
+    let final_url = page.record.final_url.clone();
+    if let Some(status) = page.record.status {
+        if !(200..300).contains(&status) {
+            warnings.push(format!("page answered with http status {}", status));
         }
     }
+    let snapshot = save_fetched_page(client, page, base_path).await?;
+    Ok(ArchiveResult::archived(
+        &source_url,
+        final_url,
+        snapshot,
+        warnings,
+    ))
 }
 
-pub async fn save_page(
+/// public function:
+/// writes the page, every asset it links to (and what their css points at)
+/// and the optional screenshot into a gzip-per-record warc file in the snapshot directory.
+/// page_response is the original http exchange for the page if there was one,
+/// otherwise the (rendered) body is stored as a resource record.
+/// metadata.json and manifest.json (every resource, failed ones with their error)
+/// go next to it. returns the path to archive.warc.gz
+pub async fn save_warc(
+    client: &HttpClient,
     html_record: HtmlRecord,
     base_path: &str,
+    page_response: Option<RawResponse>,
     screenshot: Option<Vec<u8>>,
-) -> Result<String, String> {
-    let mut body = html_record.body.clone();
-    let url = Url::parse(&html_record.origin).unwrap();
-    let host_name = url.host().unwrap().to_string();
-    let mut url_path = url.path().to_string();
-    let mut base_path = base_path.to_string();
-
-    if !base_path.ends_with('/') {
-        base_path.push('/');
+) -> Result<String, Error> {
+    let directory = snapshot_directory(client, &html_record, base_path)?;
+    fs::create_dir_all(directory.clone())?;
+
+    let warc_name = "archive.warc.gz";
+    let mut writer = WarcWriter::create(&format!("{}/{}", directory, warc_name))?;
+
+    let page_id = match page_response {
+        Some(raw) => writer.write_exchange(&raw)?,
+        None => writer.write_resource(
+            &html_record.origin,
+            "text/html; charset=utf-8",
+            html_record.body.as_bytes().to_vec(),
+        )?,
+    };
+
+    //page links first, then whatever the stylesheets among them point at,
+    //a window of max_concurrent_downloads at a time so few bodies wait in memory
+    let mut links: Vec<String> = vec![];
+    let mut stylesheets: HashSet<String> = HashSet::new();
+    if let Some(css_links) = html_record.get_css_links() {
+        stylesheets.extend(css_links.into_iter().map(|link| link.1));
+    }
+    for page_links in [
+        html_record.get_image_links(),
+        html_record.get_css_links(),
+        html_record.get_js_links(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        links.extend(page_links.into_iter().map(|link| link.1));
+    }
+    if let Some(resource_links) = html_record.get_resource_links() {
+        links.extend(resource_links.into_iter().map(|link| link.1));
+    }
+    let mut seen: HashSet<String> = HashSet::new();
+    links.retain(|link| seen.insert(link.clone()));
+
+    let mut manifest = Manifest::default();
+    let mut fields: Vec<(String, String)> = vec![];
+    let mut referrers: HashMap<String, String> = HashMap::new();
+    while !links.is_empty() {
+        let mut found: Vec<String> = vec![];
+        for window in links.chunks(client.config.max_concurrent_downloads.max(1)) {
+            let mut results = fetch_raws(client, window).await;
+            for link in window {
+                let raw = match results.remove(link) {
+                    Some(Ok(raw)) => raw,
+                    Some(Err(e)) => {
+                        manifest.push(ResourceEntry::failed(link, &e));
+                        continue;
+                    }
+                    None => continue,
+                };
+                writer.write_exchange(&raw)?;
+                fields.push(("outlink".to_string(), link.clone()));
+                if raw.status != 200 {
+                    let e = Error::HttpStatus {
+                        url: link.clone(),
+                        status: raw.status,
+                    };
+                    manifest.push(ResourceEntry::failed(link, &e));
+                    continue;
+                }
+                let content_type = raw.header("content-type");
+                let mime = guess_mime(&raw.url, content_type, &raw.body);
+                manifest.push(ResourceEntry::saved(
+                    link, warc_name, &mime, raw.status, &raw.body,
+                ));
+
+                if mime != "text/css" && !stylesheets.contains(link) {
+                    continue;
+                }
+                let referrer = referrers
+                    .get(link)
+                    .cloned()
+                    .or_else(|| html_record.charset.clone());
+                let (css, encoding) = decode_css(&raw.body, content_type, referrer.as_deref());
+                let base = Url::parse(&raw.url)?;
+                for reference in css_references(&css) {
+                    let absolute = match base.join(&reference.url) {
+                        Ok(url) => url.to_string(),
+                        Err(_) => continue,
+                    };
+                    if seen.insert(absolute.clone()) {
+                        if reference.import {
+                            stylesheets.insert(absolute.clone());
+                        }
+                        referrers.insert(absolute.clone(), encoding.name().to_string());
+                        found.push(absolute);
+                    }
+                }
+            }
+        }
+        links = found;
     }
-    if !url_path.ends_with('/') {
-        url_path.push('/');
+
+    if let Some(image) = screenshot {
+        let screenshot_id = writer.write_resource(
+            &format!("urn:screenshot:{}", html_record.origin),
+            "image/png",
+            image,
+        )?;
+        fields.push(("screenshot".to_string(), screenshot_id));
     }
 
-    let directory = format!(
-        "{}{}{}{}",
-        base_path, host_name, url_path, html_record.date_time
-    );
+    writer.write_metadata(&html_record.origin, &page_id, fields)?;
+    write_metadata(&directory, &html_record)?;
+
+    let snapshot = Snapshot {
+        id: html_record.snapshot_id.clone(),
+        url: html_record.origin.clone(),
+        date_time: html_record.date_time,
+        directory,
+        index_path: writer.path.clone(),
+        screenshot_path: None,
+        pdf_path: None,
+        resources: manifest.resources,
+    };
+    snapshot.write_manifest()?;
+
+    Ok(writer.path)
+}
+
+pub async fn save_page(
+    client: &HttpClient,
+    html_record: HtmlRecord,
+    base_path: &str,
+    screenshot: Option<Vec<u8>>,
+) -> Result<Snapshot, Error> {
+    let mut page = FetchedPage::new(html_record);
+    page.screenshot = screenshot;
+    save_fetched_page(client, page, base_path).await
+}
 
-    assert!(fs::create_dir_all(directory.clone()).is_ok());
+///save_page for everything a fetcher brought back: the screenshot and pdf
+/// are written next to the page and the assets in page.resources are used
+/// as already downloaded.
+pub(crate) async fn save_fetched_page(
+    client: &HttpClient,
+    page: FetchedPage,
+    base_path: &str,
+) -> Result<Snapshot, Error> {
+    let FetchedPage {
+        record: html_record,
+        screenshot,
+        pdf,
+        resources,
+        ..
+    } = page;
+    let directory = snapshot_directory(client, &html_record, base_path)?;
+    //original attribute value -> local path, applied to the dom at the end
+    let mut image_map: HashMap<String, String> = HashMap::new();
+    let mut css_map: HashMap<String, String> = HashMap::new();
+    let mut js_map: HashMap<String, String> = HashMap::new();
+    let mut style_map: HashMap<String, String> = HashMap::new();
+    let mut resource_map: HashMap<String, String> = HashMap::new();
+    let objects = if client.config.object_store {
+        Some(ObjectStore::new(base_path))
+    } else {
+        None
+    };
+    let mut assets = AssetWriter::new(client, &directory, objects);
+    assets
+        .prefetched
+        .extend(resources.into_iter().map(|(url, asset)| (url, Ok(asset))));
+
+    fs::create_dir_all(directory.clone())?;
+
+    //everything the page links to is queued up front and downloaded concurrently
+    //a window at a time, then handled below in document order so the local names
+    //don't depend on timing
+    let mut asset_links: Vec<String> = vec![];
+    for links in [
+        html_record.get_image_links(),
+        html_record.get_css_links(),
+        html_record.get_js_links(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        asset_links.extend(links.into_iter().map(|link| link.1));
+    }
+    if let Some(resource_links) = html_record.get_resource_links() {
+        asset_links.extend(resource_links.into_iter().map(|link| link.1));
+    }
+    assets.enqueue(&asset_links, false);
 
     if let Some(t_image_links) = html_record.get_image_links() {
-        assert!(fs::create_dir_all(format!("{}/images", directory)).is_ok());
+        fs::create_dir_all(format!("{}/images", directory))?;
         for link in t_image_links {
-            if let Ok(img_bytes) = fetch_image_bytes(&link.1).await {
-                if let Ok(tmp_image) = image::load_from_memory(&img_bytes) {
-                    if let Some(file_name) = get_file_name(&link.1) {
-                        let fqn = format!("{}/images/{}", directory, file_name);
-                        if tmp_image.save(fqn).is_ok() {
-                            let body_replacement_text = format!("./images/{}", file_name);
-                            body = body.replace(&link.0, &body_replacement_text);
-                        }
-                    } else {
-                        let mut file_name = random_name_generator();
-                        file_name.push_str(".png");
-                        let fqn = format!("{}/images/{}", directory, file_name);
-
-                        if tmp_image
-                            .save_with_format(fqn, image::ImageFormat::Png)
-                            .is_ok()
-                        {
-                            let body_replacement_text = format!("./images/{}", file_name);
-                            body = body.replace(&link.0, &body_replacement_text);
-                        }
+            //the same url spelled another way, already stored
+            if let Some(local) = assets.saved.get(&link.1) {
+                image_map.insert(link.0, format!("./{}", encode_path(local)));
+                continue;
+            }
+            //stored byte for byte, svg/avif/ico/animated images included
+            let asset = match assets.fetch(&link.1).await {
+                Some(asset) => asset,
+                None => continue,
+            };
+            let local = assets.claim(format!("images/{}", image_file_name(&link.1, &asset)));
+            let file_name = local.trim_start_matches("images/").to_string();
+            match assets.write(&local, &asset.bytes) {
+                Ok(()) => {
+                    image_map.insert(link.0, format!("./{}", encode_path(&local)));
+                    assets
+                        .manifest
+                        .push(saved_entry(&link.1, &local, &asset, &asset.bytes));
+                    assets.saved.insert(link.1, local);
+                    if let Some(size) = client.config.thumbnail_size {
+                        save_thumbnail(&directory, &file_name, &asset.bytes, size);
                     }
                 }
+                Err(e) => assets.manifest.push(ResourceEntry::failed(&link.1, &e)),
             }
         }
     }
 
-    //get css
+    //get css, along with the fonts/images/imports it points at
     if let Some(t_css_links) = html_record.get_css_links() {
-        assert!(fs::create_dir_all(format!("{}/css", directory)).is_ok());
+        fs::create_dir_all(format!("{}/css", directory))?;
         for link in t_css_links {
-            let file_name = match get_file_name(&link.1) {
-                Some(e) => e,
-                None => {
-                    let mut file = random_name_generator();
-                    file.push_str(".css");
-                    file
-                }
+            if let Some(local) = assets.saved.get(&link.1) {
+                css_map.insert(link.0, format!("./{}", encode_path(local)));
+                continue;
+            }
+            let asset = match assets.fetch(&link.1).await {
+                Some(asset) => asset,
+                None => continue,
             };
-            if let Ok(css) = fetch_string_resource(&link.1).await {
-                let fqn = format!("{}/css/{}", directory, file_name);
-                let mut file = File::create(fqn).unwrap();
-                if file.write(css.as_bytes()).is_ok() {
-                    let body_replacement_text = format!("./css/{}", file_name);
-                    body = body.replace(&link.0, &body_replacement_text);
+            let local = assets.claim(format!("css/{}", local_file_name(&link.1, "css")));
+            assets.saved.insert(link.1.clone(), local.clone());
+            let (css, charset) = asset.css_text(html_record.charset.as_deref());
+            let css = assets
+                .localize_css(&css, &link.1, "../", Some(charset))
+                .await?;
+            match assets.write(&local, css.as_bytes()) {
+                Ok(()) => {
+                    css_map.insert(link.0, format!("./{}", encode_path(&local)));
+                    assets
+                        .manifest
+                        .push(saved_entry(&link.1, &local, &asset, css.as_bytes()));
+                }
+                Err(e) => {
+                    assets.saved.remove(&link.1);
+                    assets.manifest.push(ResourceEntry::failed(&link.1, &e));
                 }
             }
         }
     }
 
+    if let Some(styles) = html_record.get_inline_styles() {
+        for style in styles {
+            let localized = assets
+                .localize_css(
+                    &style,
+                    &html_record.origin,
+                    "./",
+                    html_record.charset.as_deref(),
+                )
+                .await?;
+            if localized != style {
+                style_map.insert(style, localized);
+            }
+        }
+    }
+
     //get js
     if let Some(t_js_links) = html_record.get_js_links() {
-        assert!(fs::create_dir(format!("{}/js", directory)).is_ok());
+        fs::create_dir_all(format!("{}/js", directory))?;
         for link in t_js_links {
+            if let Some(local) = assets.saved.get(&link.1) {
+                js_map.insert(link.0, format!("./{}", encode_path(local)));
+                continue;
+            }
+            let asset = match assets.fetch(&link.1).await {
+                Some(asset) => asset,
+                None => continue,
+            };
             let file_name = match get_file_name(&link.1) {
                 Some(e) => e,
                 None => {
-                    let mut file = random_name_generator();
+                    let mut file = url_name(&link.1);
                     file.push_str(".js");
                     file
                 }
             };
-            if let Ok(css) = fetch_string_resource(&link.1).await {
-                let fqn = format!("{}/js/{}", directory, file_name);
+            let local = assets.claim(format!("js/{}", file_name));
+            match assets.write(&local, &asset.bytes) {
+                Ok(()) => {
+                    js_map.insert(link.0, format!("./{}", encode_path(&local)));
+                    assets
+                        .manifest
+                        .push(saved_entry(&link.1, &local, &asset, &asset.bytes));
+                    assets.saved.insert(link.1, local);
+                }
+                Err(e) => assets.manifest.push(ResourceEntry::failed(&link.1, &e)),
+            }
+        }
+    }
 
-                if let Ok(mut output) = File::create(fqn) {
-                    if output.write(css.as_bytes()).is_ok() {
-                        let body_replacement_text = format!("./js/{}", file_name);
-                        body = body.replace(&link.0, &body_replacement_text);
-                    }
+    //icons, manifests, preloads, media and embeds, each kind in its own folder
+    if let Some(resource_links) = html_record.get_resource_links() {
+        for (raw, link, kind) in resource_links {
+            if let Some(local) = assets.saved.get(&link) {
+                resource_map.insert(raw, format!("./{}", encode_path(local)));
+                continue;
+            }
+            let asset = match assets.fetch(&link).await {
+                Some(asset) => asset,
+                None => continue,
+            };
+            let (local, bytes) = if kind == ResourceKind::Style {
+                let local = assets.claim(format!("css/{}", local_file_name(&link, "css")));
+                assets.saved.insert(link.clone(), local.clone());
+                let (css, charset) = asset.css_text(html_record.charset.as_deref());
+                let css = assets
+                    .localize_css(&css, &link, "../", Some(charset))
+                    .await?;
+                (local, css.into_bytes())
+            } else {
+                let file_name = match kind {
+                    ResourceKind::Icon | ResourceKind::Image => image_file_name(&link, &asset),
+                    _ => local_file_name(&link, "bin"),
+                };
+                let local = assets.claim(format!("{}/{}", kind.directory(), file_name));
+                (local, asset.bytes.to_vec())
+            };
+            match assets.write(&local, &bytes) {
+                Ok(()) => {
+                    assets
+                        .manifest
+                        .push(saved_entry(&link, &local, &asset, &bytes));
+                    assets.saved.insert(link, local.clone());
+                    resource_map.insert(raw, format!("./{}", encode_path(&local)));
+                }
+                Err(e) => {
+                    assets.saved.remove(&link);
+                    assets.manifest.push(ResourceEntry::failed(&link, &e));
                 }
             }
         }
     }
+    assets.drain().await?;
     //write screenshot
+    let mut screenshot_path: Option<String> = None;
     if let Some(image) = screenshot {
         let fqn_png = format!("{}/screenshot.png", directory);
-        let mut file_png = File::create(fqn_png).unwrap();
-        assert!(file_png.write(&image).is_ok());
+        let mut file_png = File::create(fqn_png.clone())?;
+        file_png.write_all(&image)?;
+        screenshot_path = Some(fqn_png);
+    }
+    //write pdf
+    let mut pdf_path: Option<String> = None;
+    if let Some(pdf) = pdf {
+        let fqn_pdf = format!("{}/page.pdf", directory);
+        File::create(fqn_pdf.clone())?.write_all(&pdf)?;
+        pdf_path = Some(fqn_pdf);
+    }
+
+    //point the attributes at the local copies, nothing else in the document changes
+    let mut rewriter = DomRewriter::new(&html_record);
+    for (tag, attribute, is_srcset) in IMAGE_ATTRIBUTES {
+        if is_srcset {
+            rewriter.rewrite_srcset(tag, attribute, &image_map)?;
+        } else {
+            rewriter.rewrite_attribute(tag, attribute, &image_map)?;
+        }
+    }
+    rewriter.promote_lazy(&image_map)?;
+    rewriter.rewrite_attribute("link[rel~=stylesheet]", "href", &css_map)?;
+    rewriter.rewrite_attribute("script", "src", &js_map)?;
+    for (tag, attribute, _) in RESOURCE_ATTRIBUTES {
+        rewriter.rewrite_attribute(tag, attribute, &resource_map)?;
     }
+    rewriter.rewrite_styles(&style_map)?;
+    rewriter.declare_utf8()?;
+    let body = rewriter.html();
 
     //write html
     let fqn_html = format!("{}/index.html", directory);
-    let mut file_html = File::create(fqn_html.clone()).unwrap();
-    if file_html.write(body.as_bytes()).is_ok() {
-        Ok(fqn_html)
-    } else {
-        Err("error archiving site".to_string())
+    let mut file_html = File::create(fqn_html.clone())?;
+    file_html.write_all(body.as_bytes())?;
+    write_metadata(&directory, &html_record)?;
+
+    let snapshot = Snapshot {
+        id: html_record.snapshot_id.clone(),
+        url: html_record.origin.clone(),
+        date_time: html_record.date_time,
+        directory,
+        index_path: fqn_html,
+        screenshot_path,
+        pdf_path,
+        resources: assets.manifest.resources,
+    };
+    snapshot.write_manifest()?;
+
+    Ok(snapshot)
+}
+
+///everything save_page stores besides the page itself goes through here:
+/// downloads (prefetched or not), unique local names, the object store
+/// and the manifest. localize_css also pulls in what stylesheets point at
+/// (fonts, background images, @imports) and rewrites the references.
+struct AssetWriter<'a> {
+    client: &'a HttpClient,
+    directory: String,
+    ///absolute url -> path relative to the snapshot directory,
+    /// save_page adds everything else it stores so nothing is fetched twice
+    saved: HashMap<String, String>,
+    ///imported sheets not localized yet: (asset, relative path, encoding of the importing sheet)
+    pending: Vec<(Asset, String, Option<String>)>,
+    ///everything save_page and localize_css tried to archive
+    manifest: Manifest,
+    ///relative paths already handed out, lowercased for case insensitive filesystems
+    taken: HashSet<String>,
+    ///shared store the files are linked from, none writes plain files
+    objects: Option<ObjectStore>,
+    ///urls still to download, in the order fetch will ask for them
+    queue: Vec<String>,
+    ///downloads done ahead of time, taken by fetch
+    prefetched: HashMap<String, Result<Asset, Error>>,
+    ///urls that could not be downloaded, already in the manifest
+    failed: HashSet<String>,
+}
+
+impl<'a> AssetWriter<'a> {
+    fn new(client: &'a HttpClient, directory: &str, objects: Option<ObjectStore>) -> Self {
+        AssetWriter {
+            client,
+            directory: directory.to_string(),
+            saved: HashMap::new(),
+            pending: vec![],
+            manifest: Manifest::default(),
+            taken: HashSet::new(),
+            objects,
+            queue: vec![],
+            prefetched: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    ///queues urls for fetch, in the order they will be asked for.
+    /// front puts them before everything already queued (the references
+    /// of a stylesheet, fetched right away).
+    fn enqueue(&mut self, urls: &[String], front: bool) {
+        let mut missing: Vec<String> = vec![];
+        for url in urls {
+            if !self.saved.contains_key(url)
+                && !self.prefetched.contains_key(url)
+                && !self.failed.contains(url)
+                && !missing.contains(url)
+            {
+                missing.push(url.clone());
+            }
+        }
+        self.queue.retain(|url| !missing.contains(url));
+        if front {
+            self.queue.splice(0..0, missing);
+        } else {
+            self.queue.extend(missing);
+        }
+    }
+
+    ///the download of url, none when it failed (the failure goes into the manifest once).
+    /// a queued url is downloaded together with the ones queued after it, at most
+    /// max_concurrent_downloads at a time, so only that many bodies wait in memory.
+    async fn fetch(&mut self, url: &str) -> Option<Asset> {
+        if self.failed.contains(url) {
+            return None;
+        }
+        let result = match self.prefetched.remove(url) {
+            Some(result) => result,
+            None => match self.queue.iter().position(|queued| queued == url) {
+                Some(start) => {
+                    let end = (start + self.client.config.max_concurrent_downloads.max(1))
+                        .min(self.queue.len());
+                    let window: Vec<String> = self
+                        .queue
+                        .drain(start..end)
+                        .filter(|queued| {
+                            queued == url
+                                || !(self.saved.contains_key(queued)
+                                    || self.failed.contains(queued))
+                        })
+                        .collect();
+                    self.prefetched
+                        .extend(fetch_assets(self.client, &window).await);
+                    match self.prefetched.remove(url) {
+                        Some(result) => result,
+                        None => fetch_asset(self.client, url).await,
+                    }
+                }
+                None => fetch_asset(self.client, url).await,
+            },
+        };
+        match result {
+            Ok(asset) => Some(asset),
+            Err(e) => {
+                self.manifest.push(ResourceEntry::failed(url, &e));
+                self.failed.insert(url.to_string());
+                None
+            }
+        }
+    }
+
+    ///writes the file at local, through the object store when there is one.
+    fn write(&self, local: &str, bytes: &[u8]) -> Result<(), Error> {
+        match &self.objects {
+            Some(objects) => {
+                objects.link(bytes, &format!("{}/{}", self.directory, local))?;
+                Ok(())
+            }
+            None => write_asset(&self.directory, local, bytes),
+        }
+    }
+
+    ///reserves a relative path for a new file, two different urls with the
+    /// same file name get "logo.png", "logo-2.png"... instead of overwriting each other.
+    fn claim(&mut self, local: String) -> String {
+        let mut candidate = local.clone();
+        let mut number = 1;
+        while self.taken.contains(&candidate.to_lowercase()) {
+            number += 1;
+            candidate = numbered_file_name(&local, number);
+        }
+        self.taken.insert(candidate.to_lowercase());
+        candidate
+    }
+
+    ///base_url is where the css came from, relative urls are joined onto it.
+    /// prefix gets from the css back to the snapshot root ("./" inline, "../" in css/).
+    /// charset is what the css was decoded from, sheets it imports without a charset
+    /// of their own are read with it.
+    async fn localize_css(
+        &mut self,
+        css: &str,
+        base_url: &str,
+        prefix: &str,
+        charset: Option<&str>,
+    ) -> Result<String, Error> {
+        let base = Url::parse(base_url)?;
+        let mut replacements: HashMap<String, String> = HashMap::new();
+        let references = css_references(css);
+        let absolute_urls: Vec<String> = references
+            .iter()
+            .filter_map(|reference| base.join(&reference.url).ok())
+            .map(|url| url.to_string())
+            .collect();
+        self.enqueue(&absolute_urls, true);
+
+        for reference in references {
+            let absolute = match base.join(&reference.url) {
+                Ok(url) => url.to_string(),
+                Err(_) => continue,
+            };
+            let local = match self.saved.get(&absolute) {
+                Some(local) => local.clone(),
+                None => {
+                    let asset = match self.fetch(&absolute).await {
+                        Some(asset) => asset,
+                        None => continue,
+                    };
+                    let sub_directory = css_asset_directory(&absolute, reference.import);
+                    let local = if sub_directory == "css" {
+                        let local =
+                            self.claim(format!("css/{}", local_file_name(&absolute, "css")));
+                        self.pending
+                            .push((asset, local.clone(), charset.map(String::from)));
+                        local
+                    } else {
+                        let file_name = if sub_directory == "images" {
+                            image_file_name(&absolute, &asset)
+                        } else {
+                            local_file_name(&absolute, "bin")
+                        };
+                        let local = self.claim(format!("{}/{}", sub_directory, file_name));
+                        if let Err(e) = self.write(&local, &asset.bytes) {
+                            self.manifest.push(ResourceEntry::failed(&absolute, &e));
+                            continue;
+                        }
+                        self.manifest
+                            .push(saved_entry(&absolute, &local, &asset, &asset.bytes));
+                        local
+                    };
+                    self.saved.insert(absolute, local.clone());
+                    local
+                }
+            };
+            replacements.insert(reference.url, format!("{}{}", prefix, encode_path(&local)));
+        }
+
+        Ok(rewrite_css(css, &replacements))
+    }
+
+    ///localizes and writes the @imported sheets (and whatever they import)
+    /// until none are left, the saved map stops import cycles.
+    async fn drain(&mut self) -> Result<(), Error> {
+        while let Some((asset, local, referrer)) = self.pending.pop() {
+            let (css, charset) = asset.css_text(referrer.as_deref());
+            let css = self
+                .localize_css(&css, &asset.url, "../", Some(charset))
+                .await?;
+            match self.write(&local, css.as_bytes()) {
+                Ok(()) => {
+                    self.manifest
+                        .push(saved_entry(&asset.url, &local, &asset, css.as_bytes()))
+                }
+                Err(e) => self.manifest.push(ResourceEntry::failed(&asset.url, &e)),
+            }
+        }
+        Ok(())
     }
 }
 
+///manifest entry for an asset written to local, bytes is what ended up on disk.
+fn saved_entry(url: &str, local: &str, asset: &Asset, bytes: &[u8]) -> ResourceEntry {
+    let mime = guess_mime(url, asset.content_type.as_deref(), &asset.bytes);
+    ResourceEntry::saved(url, local, &mime, asset.status, bytes)
+}
+
+///metadata.json next to the page: status, headers, redirects, timing.
+pub(crate) fn write_metadata(directory: &str, html_record: &HtmlRecord) -> Result<(), Error> {
+    let metadata = serde_json::to_string_pretty(&html_record.metadata())
+        .map_err(|e| Error::Parse(e.to_string()))?;
+    File::create(format!("{}/metadata.json", directory))?.write_all(metadata.as_bytes())?;
+    Ok(())
+}
+
+///writes bytes to directory/local, creating the subdirectory on the way.
+fn write_asset(directory: &str, local: &str, bytes: &[u8]) -> Result<(), Error> {
+    let fqn = format!("{}/{}", directory, local);
+    if let Some((parent, _)) = fqn.rsplit_once('/') {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(fqn)?.write_all(bytes)?;
+    Ok(())
+}
+
+///file name from the url, or one made from a hash of the url when it has a query
+/// or no usable last segment. the hashed name keeps the extension of the
+/// url path if there is one, default_extension otherwise.
+fn local_file_name(link: &str, default_extension: &str) -> String {
+    match get_file_name(link) {
+        Some(file_name) => file_name,
+        None => {
+            let extension = Url::parse(link)
+                .ok()
+                .and_then(|url| {
+                    let last = url.path().rsplit('/').next()?.to_string();
+                    let (_, extension) = last.rsplit_once('.')?;
+                    Some(extension.to_string())
+                })
+                .filter(|extension| !extension.is_empty())
+                .unwrap_or_else(|| default_extension.to_string());
+            sanitize_component(&format!("{}.{}", url_name(link), extension))
+        }
+    }
+}
+
+///file name for a downloaded image, makes sure it ends in the extension
+/// of what was actually downloaded (content type or magic bytes)
+/// so it opens right from disk.
+fn image_file_name(link: &str, asset: &Asset) -> String {
+    let file_name = get_file_name(link).unwrap_or_else(|| url_name(link));
+    match image_extension(asset.content_type.as_deref(), &asset.bytes) {
+        Some(extension) => {
+            let lower = file_name.to_lowercase();
+            let has_extension = lower.ends_with(&format!(".{}", extension))
+                || (extension == "jpg" && lower.ends_with(".jpeg"))
+                || (extension == "tiff" && lower.ends_with(".tif"));
+            if has_extension {
+                file_name
+            } else {
+                format!("{}.{}", file_name, extension)
+            }
+        }
+        None => file_name,
+    }
+}
+
+///optional thumbnail, only for formats the image crate can decode,
+/// the archived original is never touched.
+fn save_thumbnail(directory: &str, file_name: &str, bytes: &[u8], size: u32) {
+    if let Ok(image) = image::load_from_memory(bytes) {
+        if fs::create_dir_all(format!("{}/thumbnails", directory)).is_ok() {
+            let _ = image.thumbnail(size, size).save_with_format(
+                format!("{}/thumbnails/{}.png", directory, file_name),
+                image::ImageFormat::Png,
+            );
+        }
+    }
+}
+
+///base_path joined with the directory the configured layout picks for the record.
+pub(crate) fn snapshot_directory(
+    client: &HttpClient,
+    html_record: &HtmlRecord,
+    base_path: &str,
+) -> Result<String, Error> {
+    //whatever the layout returns, every component is made safe and ".." can't leave base_path
+    let relative = sanitize_relative_path(&client.config.layout.relative_directory(html_record)?);
+    Ok(format!("{}/{}", base_path.trim_end_matches('/'), relative))
+}
+
+///sanitized last path segment of the url, none when the url has a query
+/// or ends in '/' (there is no file name to keep then).
 fn get_file_name(link: &str) -> Option<String> {
-    let urlp = Url::parse(link).unwrap();
+    let urlp = Url::parse(link).ok()?;
     if urlp.query().is_some() {
         return None;
-    } else if let Some(segment_vector) = urlp.path_segments().map(|c| c.collect::<Vec<_>>()) {
-        let segment_file = *segment_vector.last().unwrap();
-        return Some(segment_file.to_string());
     }
-    None
+    let segment_file = urlp.path_segments()?.next_back()?;
+    if segment_file.is_empty() {
+        return None;
+    }
+    Some(sanitize_component(segment_file))
 }
 
 pub fn get_capabilities() -> Map<String, Value> {
@@ -292,19 +945,30 @@ pub fn get_capabilities() -> Map<String, Value> {
     caps
 }
 
-fn random_name_generator() -> String {
-    let s: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(7)
-        .map(char::from)
-        .collect();
-    s
+///name for a url without a usable file name, the same url always gets the same one.
+fn url_name(link: &str) -> String {
+    sha256_hex(link.as_bytes())[..16].to_string()
 }
 
-pub fn replace_encoded_chars(body: String) -> String {
-    body.replace("&lt;", "<")
-        .replace("&gt;", ">")
-        .replace("&quot;", "\"")
-        .replace("&amp;", "&")
-        .replace("&apos", "\'")
+impl Archiver<HybridFetcher> {
+    pub async fn new(connection_string: &str) -> Result<Self, Error> {
+        Ok(Archiver::with_fetcher(
+            HybridFetcher::new(connection_string).await?,
+        ))
+    }
+
+    pub async fn with_config(
+        connection_string: &str,
+        config: ArchiverConfig,
+        browser_config: BrowserConfig,
+        rules: RenderRules,
+    ) -> Result<Self, Error> {
+        Ok(Archiver::with_fetcher(
+            HybridFetcher::with_config(connection_string, config, browser_config, rules).await?,
+        ))
+    }
+
+    pub async fn close(self) -> Result<(), Error> {
+        self.fetcher.close().await
+    }
 }